@@ -0,0 +1,103 @@
+use glam::Vec4;
+
+use crate::rasterizer::BlendMode;
+
+/// 空链表
+const NIL: u32 = u32::MAX;
+
+struct Fragment {
+    /// 与深度缓冲相同，为 NDC 下的 z，越大离相机越近
+    depth: f32,
+    color: Vec4,
+    next: u32,
+}
+
+/// A-buffer 中屏幕上一个 tile 的部分，为每个采样点保存半透明片元的链表
+///
+/// 每个 tile 有独立的结点池，这样分 tile 并行时各线程互不干扰，
+/// 并且单线程和并行两种模式下溢出的片元完全相同
+pub(crate) struct FragmentTile {
+    width: usize,
+    samples: usize,
+    /// 每个采样点链表的头结点下标
+    heads: Vec<u32>,
+    nodes: Vec<Fragment>,
+    /// 结点池的容量，超出后新的片元不再保存
+    capacity: usize,
+    /// 因结点池已满而没能保存的片元数
+    pub(crate) overflowed: usize,
+}
+
+impl FragmentTile {
+    pub(crate) fn new(width: usize, height: usize, samples: usize, capacity: usize) -> Self {
+        Self {
+            width,
+            samples,
+            heads: vec![NIL; width * height * samples],
+            nodes: Vec::new(),
+            capacity,
+            overflowed: 0,
+        }
+    }
+
+    /// `(x, y)` 为 tile 内的局部坐标，`s` 为采样点序号
+    #[inline]
+    fn index(&self, x: usize, y: usize, s: usize) -> usize {
+        (y * self.width + x) * self.samples + s
+    }
+
+    /// 保存一个片元。结点池已满时返回 `false`，由调用者决定如何处理
+    ///
+    /// 光栅化器会把没能保存的片元直接与颜色缓冲混合。由于已保存的片元在 `resolve` 时
+    /// 才混合到其上，这相当于不论深度如何，都把溢出的片元当作在所有已保存片元的后面
+    pub(crate) fn push(&mut self, x: usize, y: usize, s: usize, depth: f32, color: Vec4) -> bool {
+        if self.nodes.len() >= self.capacity {
+            self.overflowed += 1;
+            return false;
+        }
+        let index = self.index(x, y, s);
+        self.nodes.push(Fragment {
+            depth,
+            color,
+            next: self.heads[index],
+        });
+        self.heads[index] = (self.nodes.len() - 1) as u32;
+        true
+    }
+
+    /// 将采样点上的片元由远到近依次与 `dst` 混合，深度相同时先保存的片元先混合
+    ///
+    /// `scratch` 用于排序，避免每个采样点都重新分配内存
+    pub(crate) fn resolve(
+        &self,
+        x: usize,
+        y: usize,
+        s: usize,
+        blend: BlendMode,
+        mut dst: Vec4,
+        scratch: &mut Vec<(f32, Vec4)>,
+    ) -> Vec4 {
+        scratch.clear();
+        let mut node = self.heads[self.index(x, y, s)];
+        while node != NIL {
+            let f = &self.nodes[node as usize];
+            scratch.push((f.depth, f.color));
+            node = f.next;
+        }
+        // 链表是倒序的，翻转后再稳定排序
+        scratch.reverse();
+        scratch.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for &(_, color) in scratch.iter() {
+            dst = blend.blend(color, dst);
+        }
+        dst
+    }
+
+    pub(crate) fn clear(&mut self) {
+        if !self.nodes.is_empty() {
+            self.heads.fill(NIL);
+            self.nodes.clear();
+        }
+        self.overflowed = 0;
+    }
+}
@@ -1,6 +1,7 @@
 use lab_graphics::object::Object;
-use lab_graphics::rasterizer::Rasterizer;
+use lab_graphics::rasterizer::{CullMode, Rasterizer};
 use lab_graphics::shaders::{BlinnPhongShader, BumpShader, DisplacementShader, TextureShader};
+use lab_graphics::texture::Texture;
 use lab_graphics::{color, transform};
 
 use glam::Vec3;
@@ -88,10 +89,13 @@ fn main() {
 
     let mut rst = Rasterizer::new(WIDTH, HEIGHT, _texture_shader);
     rst.view(transform::view(eye_pos, angle_alpha, angle_beta))
-        .projection(transform::perspective(45., 1., z_near, z_far));
+        .projection(transform::perspective(45., 1., z_near, z_far))
+        .cull_mode(CullMode::Back);
 
-    let spot = Object::load_obj("model/spot_triangulated_good.obj", "model/spot_texture.png")
+    let spot_texture = image::open("model/spot_texture.png").unwrap();
+    let spot = Object::load_obj("model/spot_triangulated_good.obj")
         .unwrap()
+        .texture(Texture::new(spot_texture))
         .model(transform::model(0., 0., 0., 140., 2.5));
     let objects = vec![spot];
 
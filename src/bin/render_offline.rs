@@ -0,0 +1,381 @@
+//! 不需要窗口的离线渲染器，将渲染结果保存为 PNG 或 PPM 图像
+//!
+//! 示例：
+//!
+//! ```shell
+//! cargo run --release --bin render_offline -- --shader phong --eye 0,0,8 --frames 36 --spin 10 --output out/spot.png
+//! ```
+
+use lab_graphics::color::{ColorSpace, ToneMapping};
+use lab_graphics::environment::Environment;
+use lab_graphics::object::{LoadOptions, NormalMode, Object};
+use lab_graphics::rasterizer::{CullMode, Msaa, Rasterizer, ResolveFilter};
+use lab_graphics::shaders::{
+    BlinnPhongShader, BumpShader, DisplacementShader, NormalMapFormat, NormalMapShader,
+    NormalShader, PbrShader, Shader, TextureShader,
+};
+use lab_graphics::texture::{Filter, Sampler, Texture, Wrap};
+use lab_graphics::transform;
+
+use anyhow::{anyhow, bail, Context, Result};
+use glam::Vec3;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const USAGE: &str = "\
+用法：render_offline [选项]
+
+  --obj <路径>         模型文件，默认 model/spot_triangulated_good.obj
+  --crease <角度>      模型没有法线时生成平滑法线，夹角超过该值的棱保持锐利，默认生成面法线
+  --texture <路径>     纹理文件，默认 model/spot_texture.png，为 none 时不使用纹理
+  --shader <名称>      texture、phong、pbr、bump、displacement、normal 或 normalmap，默认 texture
+  --filter <名称>      texture 着色器的纹理过滤方式，nearest、bilinear 或 trilinear，默认 trilinear
+  --wrap <名称>        texture 着色器的纹理寻址方式，repeat、mirrored、clamp 或 border，默认 clamp
+  --metallic <值>      pbr 着色器的金属度，默认 0
+  --roughness <值>     pbr 着色器的粗糙度，默认 0.5
+  --normal-map <路径>  normalmap 着色器的切线空间法线贴图，不指定时使用模型材质中的法线贴图
+  --normal-format <名称>  法线贴图 g 通道的约定，opengl 或 directx，默认 opengl
+  --env <路径>         等距柱状投影的环境贴图（.hdr 或 PNG），作为天空盒绘制，并为 pbr 着色器提供环境光照
+  --size <宽>x<高>     输出图像大小，默认 700x700
+  --eye <x,y,z>        视点，默认 0,0,10
+  --alpha <角度>       视线水平角，默认 0
+  --beta <角度>        视线仰角，默认 0
+  --fov <角度>         y 轴视域角度，默认 45
+  --offset <x,y,z>     模型平移，默认 0,0,0
+  --angle <角度>       模型绕 y 轴旋转的角度，默认 140
+  --scale <倍数>       模型缩放，默认 2.5
+  --frames <帧数>      渲染的帧数，默认 1
+  --spin <角度>        每一帧模型额外旋转的角度，默认 0
+  --msaa <1|2|4|8>     MSAA 采样数，默认 1
+  --ssaa <倍数>        SSAA 倍数，开启时忽略 --msaa
+  --tone-map <名称>    色调映射，clamp、reinhard 或 aces，默认 clamp
+  --exposure <档数>    曝光补偿，默认 0
+  --texture-space <名称>  纹理的色彩空间，srgb 或 linear，默认 srgb。
+                       bump、displacement 着色器的纹理是高度图，总是按 linear 读取
+  --output-space <名称>   输出图像的色彩空间，srgb 或 linear，默认 srgb。
+                       两者都设为 linear 时与不区分色彩空间的旧版本结果一致
+  --output <路径>      输出文件，按扩展名选择 .png 或 .ppm，默认 frame.png
+                       渲染多帧时会在扩展名前加上帧号";
+
+struct Options {
+    obj: PathBuf,
+    normals: NormalMode,
+    texture: Option<PathBuf>,
+    shader: String,
+    filter: Filter,
+    wrap: Wrap,
+    metallic: f32,
+    roughness: f32,
+    normal_map: Option<PathBuf>,
+    normal_format: NormalMapFormat,
+    environment: Option<PathBuf>,
+    width: usize,
+    height: usize,
+    eye_pos: Vec3,
+    angle_alpha: f32,
+    angle_beta: f32,
+    fovy: f32,
+    offset: Vec3,
+    angle: f32,
+    scale: f32,
+    frames: usize,
+    spin: f32,
+    msaa: Msaa,
+    ssaa: usize,
+    tone_mapping: ToneMapping,
+    exposure: f32,
+    texture_space: ColorSpace,
+    output_space: ColorSpace,
+    output: PathBuf,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            obj: "model/spot_triangulated_good.obj".into(),
+            normals: NormalMode::Flat,
+            texture: Some("model/spot_texture.png".into()),
+            shader: "texture".into(),
+            filter: Filter::Trilinear,
+            wrap: Wrap::ClampToEdge,
+            metallic: 0.,
+            roughness: 0.5,
+            normal_map: None,
+            normal_format: NormalMapFormat::OpenGl,
+            environment: None,
+            width: 700,
+            height: 700,
+            eye_pos: Vec3::new(0., 0., 10.),
+            angle_alpha: 0.,
+            angle_beta: 0.,
+            fovy: 45.,
+            offset: Vec3::ZERO,
+            angle: 140.,
+            scale: 2.5,
+            frames: 1,
+            spin: 0.,
+            msaa: Msaa::Off,
+            ssaa: 1,
+            tone_mapping: ToneMapping::Clamp,
+            exposure: 0.,
+            texture_space: ColorSpace::Srgb,
+            output_space: ColorSpace::Srgb,
+            output: "frame.png".into(),
+        }
+    }
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut opts = Self::default();
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            let value = args.next().ok_or_else(|| anyhow!("选项 {arg} 缺少参数"))?;
+            let value = value.as_str();
+            match arg.as_str() {
+                "--obj" => opts.obj = value.into(),
+                "--crease" => {
+                    opts.normals = NormalMode::Smooth {
+                        crease_angle: value.parse()?,
+                    }
+                }
+                "--texture" => opts.texture = (value != "none").then(|| value.into()),
+                "--shader" => opts.shader = value.into(),
+                "--filter" => {
+                    opts.filter = match value {
+                        "nearest" => Filter::Nearest,
+                        "bilinear" => Filter::Bilinear,
+                        "trilinear" => Filter::Trilinear,
+                        _ => bail!("未知的纹理过滤方式 {value}"),
+                    }
+                }
+                "--wrap" => {
+                    opts.wrap = match value {
+                        "repeat" => Wrap::Repeat,
+                        "mirrored" => Wrap::MirroredRepeat,
+                        "clamp" => Wrap::ClampToEdge,
+                        "border" => Wrap::ClampToBorder,
+                        _ => bail!("未知的纹理寻址方式 {value}"),
+                    }
+                }
+                "--metallic" => opts.metallic = value.parse()?,
+                "--roughness" => opts.roughness = value.parse()?,
+                "--normal-map" => opts.normal_map = Some(value.into()),
+                "--normal-format" => {
+                    opts.normal_format = match value {
+                        "opengl" => NormalMapFormat::OpenGl,
+                        "directx" => NormalMapFormat::DirectX,
+                        _ => bail!("未知的法线贴图格式 {value}"),
+                    }
+                }
+                "--env" => opts.environment = Some(value.into()),
+                "--size" => {
+                    let (w, h) = value
+                        .split_once('x')
+                        .ok_or_else(|| anyhow!("图像大小应形如 700x700"))?;
+                    opts.width = w.parse()?;
+                    opts.height = h.parse()?;
+                }
+                "--eye" => opts.eye_pos = parse_vec3(value)?,
+                "--alpha" => opts.angle_alpha = value.parse()?,
+                "--beta" => opts.angle_beta = value.parse()?,
+                "--fov" => opts.fovy = value.parse()?,
+                "--offset" => opts.offset = parse_vec3(value)?,
+                "--angle" => opts.angle = value.parse()?,
+                "--scale" => opts.scale = value.parse()?,
+                "--frames" => opts.frames = value.parse()?,
+                "--spin" => opts.spin = value.parse()?,
+                "--msaa" => {
+                    opts.msaa = match value {
+                        "1" => Msaa::Off,
+                        "2" => Msaa::X2,
+                        "4" => Msaa::X4,
+                        "8" => Msaa::X8,
+                        _ => bail!("不支持的 MSAA 采样数 {value}"),
+                    }
+                }
+                "--ssaa" => opts.ssaa = value.parse()?,
+                "--tone-map" => {
+                    opts.tone_mapping = match value {
+                        "clamp" => ToneMapping::Clamp,
+                        "reinhard" => ToneMapping::Reinhard,
+                        "aces" => ToneMapping::Aces,
+                        _ => bail!("未知的色调映射 {value}"),
+                    }
+                }
+                "--exposure" => opts.exposure = value.parse()?,
+                "--texture-space" => opts.texture_space = parse_color_space(value)?,
+                "--output-space" => opts.output_space = parse_color_space(value)?,
+                "--output" => opts.output = value.into(),
+                _ => bail!("未知选项 {arg}\n\n{USAGE}"),
+            }
+        }
+        if opts.width == 0 || opts.height == 0 || opts.ssaa == 0 {
+            bail!("图像大小和 SSAA 倍数必须为正");
+        }
+        if opts.frames == 0 {
+            bail!("帧数必须为正");
+        }
+        Ok(opts)
+    }
+
+    /// 第 `frame` 帧的输出路径
+    fn output_path(&self, frame: usize) -> PathBuf {
+        if self.frames == 1 {
+            return self.output.clone();
+        }
+        let stem = self
+            .output
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let mut name = format!("{stem}_{frame:04}");
+        if let Some(ext) = self.output.extension() {
+            name.push('.');
+            name.push_str(&ext.to_string_lossy());
+        }
+        self.output.with_file_name(name)
+    }
+}
+
+fn parse_vec3(s: &str) -> Result<Vec3> {
+    let v = s
+        .split(',')
+        .map(|x| x.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("无法解析坐标 {s}"))?;
+    match v[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => bail!("坐标应形如 x,y,z，得到 {s}"),
+    }
+}
+
+fn parse_color_space(s: &str) -> Result<ColorSpace> {
+    match s {
+        "srgb" => Ok(ColorSpace::Srgb),
+        "linear" => Ok(ColorSpace::Linear),
+        _ => bail!("未知的色彩空间 {s}"),
+    }
+}
+
+fn render<S: Shader>(opts: &Options, shader: S, skybox: Option<&Environment>) -> Result<()> {
+    let mut rst = if opts.ssaa > 1 {
+        Rasterizer::with_ssaa(
+            opts.width,
+            opts.height,
+            shader,
+            opts.ssaa,
+            ResolveFilter::Tent,
+        )
+    } else {
+        Rasterizer::with_msaa(opts.width, opts.height, shader, opts.msaa)
+    };
+    let aspect = opts.width as f32 / opts.height as f32;
+    rst.view(transform::view(
+        opts.eye_pos,
+        opts.angle_alpha,
+        opts.angle_beta,
+    ))
+    .projection(transform::perspective(opts.fovy, aspect, 0.1, 50.))
+    .cull_mode(CullMode::Back)
+    .tone_mapping(opts.tone_mapping)
+    .exposure(opts.exposure)
+    .output_color_space(opts.output_space);
+
+    let options = LoadOptions {
+        normals: opts.normals,
+    };
+    let mut object = Object::load_obj_with(&opts.obj, &options)?;
+    if let Some(texture) = &opts.texture {
+        let img =
+            image::open(texture).with_context(|| format!("无法加载纹理 {}", texture.display()))?;
+        // 高度图不是颜色数据，不做 sRGB 解码
+        let space = match opts.shader.as_str() {
+            "bump" | "displacement" => ColorSpace::Linear,
+            _ => opts.texture_space,
+        };
+        object.texture = Some(Texture::with_color_space(img, space));
+    }
+    for frame in 0..opts.frames {
+        let angle = opts.angle + opts.spin * frame as f32;
+        object.model = transform::model(
+            opts.offset.x,
+            opts.offset.y,
+            opts.offset.z,
+            angle,
+            opts.scale,
+        );
+        rst.clear();
+        if let Some(environment) = skybox {
+            rst.draw_skybox(environment);
+        }
+        rst.draw(&object);
+
+        let path = opts.output_path(frame);
+        save(&rst, &path)?;
+        println!("已保存 {}", path.display());
+    }
+    Ok(())
+}
+
+fn save<S: Shader>(rst: &Rasterizer<S>, path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let ext = path.extension().map(|ext| ext.to_ascii_lowercase());
+    match ext.as_ref().and_then(|ext| ext.to_str()) {
+        Some("png") => rst.save_png(path),
+        Some("ppm") => rst.save_ppm(path),
+        _ => bail!("只支持输出 .png 或 .ppm 文件：{}", path.display()),
+    }
+}
+
+fn main() -> Result<()> {
+    let opts = Options::parse(std::env::args().skip(1))?;
+    let eye_pos = opts.eye_pos;
+    let environment = opts
+        .environment
+        .as_ref()
+        .map(Environment::open)
+        .transpose()?
+        .map(Arc::new);
+    let skybox = environment.as_deref();
+    match opts.shader.as_str() {
+        "texture" => {
+            let mut shader = TextureShader::example(eye_pos);
+            shader.sampler(Sampler::new(opts.filter).wrap(opts.wrap));
+            render(&opts, shader, skybox)
+        }
+        "phong" => render(&opts, BlinnPhongShader::example(eye_pos), skybox),
+        "pbr" => {
+            let mut shader = PbrShader::example(eye_pos);
+            shader
+                .metallic(opts.metallic)
+                .roughness(opts.roughness)
+                .sampler(Sampler::new(opts.filter).wrap(opts.wrap));
+            if let Some(environment) = &environment {
+                shader.environment(environment.clone());
+            }
+            render(&opts, shader, skybox)
+        }
+        "bump" => render(&opts, BumpShader::new(eye_pos), skybox),
+        "displacement" => render(&opts, DisplacementShader::example(eye_pos), skybox),
+        "normal" => render(&opts, NormalShader, skybox),
+        "normalmap" => {
+            let mut shader = NormalMapShader::example(eye_pos);
+            shader
+                .format(opts.normal_format)
+                .sampler(Sampler::new(opts.filter).wrap(opts.wrap));
+            if let Some(path) = &opts.normal_map {
+                let img = image::open(path)
+                    .with_context(|| format!("无法加载法线贴图 {}", path.display()))?;
+                shader.normal_map(Texture::linear(img));
+            }
+            render(&opts, shader, skybox)
+        }
+        name => bail!("未知着色器 {name}"),
+    }
+}
@@ -0,0 +1,89 @@
+use glam::Vec4;
+
+use crate::{shaders::Varyings, triangle::Triangle};
+
+/// 视锥体的六个裁剪平面，以齐次坐标下的平面方程表示，点积非负即在平面内侧
+///
+/// 本仓库的透视投影中 `w` 为投影前的 `z` 坐标，可见区域满足 `w < 0`，
+/// 所以点位于视锥内等价于 x、y、z 都在 `[w, -w]` 之间
+const PLANES: [Vec4; 6] = [
+    // 左、右
+    Vec4::new(1., 0., 0., -1.),
+    Vec4::new(-1., 0., 0., -1.),
+    // 下、上
+    Vec4::new(0., 1., 0., -1.),
+    Vec4::new(0., -1., 0., -1.),
+    // 近、远。NDC 下近平面 z = 1，远平面 z = -1
+    Vec4::new(0., 0., 1., -1.),
+    Vec4::new(0., 0., -1., -1.),
+];
+
+/// 裁剪过程中的顶点，携带所有需要插值的属性
+#[derive(Clone, Copy)]
+struct ClipVertex<V> {
+    pos: Vec4,
+    varyings: V,
+}
+
+impl<V: Varyings> ClipVertex<V> {
+    /// 在齐次裁剪空间中线性插值，此时各属性与坐标仍是线性关系，无需透视校正
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            pos: self.pos.lerp(other.pos, t),
+            varyings: self.varyings * (1. - t) + other.varyings * t,
+        }
+    }
+}
+
+/// 在齐次裁剪空间中对三角形做 Sutherland–Hodgman 裁剪
+///
+/// 裁剪得到的多边形按扇形重新三角化，结果追加到 `out` 中。顶点的环绕方向保持不变。
+pub fn clip_triangle<V: Varyings>(t: &Triangle<V>, out: &mut Vec<Triangle<V>>) {
+    let mut all_inside = true;
+    for plane in PLANES {
+        let d = t.v.map(|p| plane.dot(p));
+        // 三个顶点都在同一平面外侧，整个三角形不可见
+        if d.iter().all(|&d| d < 0.) {
+            return;
+        }
+        all_inside &= d.iter().all(|&d| d >= 0.);
+    }
+    if all_inside {
+        out.push(t.clone());
+        return;
+    }
+
+    let mut polygon: Vec<ClipVertex<V>> = (0..3)
+        .map(|i| ClipVertex {
+            pos: t.v[i],
+            varyings: t.varyings[i],
+        })
+        .collect();
+    let mut next = Vec::with_capacity(polygon.len() + PLANES.len());
+    for plane in PLANES {
+        next.clear();
+        for (i, cur) in polygon.iter().enumerate() {
+            let prev = &polygon[(i + polygon.len() - 1) % polygon.len()];
+            let (d_prev, d_cur) = (plane.dot(prev.pos), plane.dot(cur.pos));
+            // 边严格跨越平面时，在交点处插入新顶点
+            if (d_prev > 0. && d_cur < 0.) || (d_prev < 0. && d_cur > 0.) {
+                next.push(prev.lerp(cur, d_prev / (d_prev - d_cur)));
+            }
+            if d_cur >= 0. {
+                next.push(*cur);
+            }
+        }
+        std::mem::swap(&mut polygon, &mut next);
+        if polygon.len() < 3 {
+            return;
+        }
+    }
+
+    for i in 1..polygon.len() - 1 {
+        let vs = [polygon[0], polygon[i], polygon[i + 1]];
+        out.push(Triangle {
+            v: vs.map(|v| v.pos),
+            varyings: vs.map(|v| v.varyings),
+        });
+    }
+}
@@ -1,20 +1,76 @@
 use glam::{vec3, Vec3};
 use rgb::alt::BGRA8;
+use std::sync::OnceLock;
 
 // 注，颜色的顺序按照 bgr 排列
 pub const WHITE: Vec3 = vec3(1., 1., 1.);
 pub const GREEN: Vec3 = vec3(0., 1., 0.);
 pub const RED: Vec3 = vec3(0., 0., 1.);
 
+/// 将 `[0, 1]` 范围内的颜色转换为 8 位颜色，超出范围的部分被截断
 pub fn to_bgra(color: Vec3) -> BGRA8 {
+    let c = color.clamp(Vec3::ZERO, Vec3::ONE) * 255. + 0.5;
     BGRA8 {
-        b: (color.x * 255.) as u8,
-        g: (color.y * 255.) as u8,
-        r: (color.z * 255.) as u8,
+        b: c.x as u8,
+        g: c.y as u8,
+        r: c.z as u8,
         a: 0,
     }
 }
 
+/// 颜色数据所在的色彩空间
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    /// 数值与光强成正比，适合光照计算，也适合法线、高度等非颜色数据
+    Linear,
+    /// 经过 sRGB 传递函数编码，普通的 PNG、JPEG 图片和显示器都使用这种编码
+    #[default]
+    Srgb,
+}
+
+/// sRGB 编码的值解码为线性值
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// 线性值编码为 sRGB
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// 将 8 位的通道值解码为线性值，用查找表避免每个纹素都计算一次幂
+pub fn decode_u8(c: u8, space: ColorSpace) -> f32 {
+    static SRGB_TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    match space {
+        ColorSpace::Linear => c as f32 / 255.,
+        ColorSpace::Srgb => SRGB_TABLE
+            .get_or_init(|| std::array::from_fn(|i| srgb_to_linear(i as f32 / 255.)))[c as usize],
+    }
+}
+
+/// 将线性颜色按 `space` 编码为 8 位颜色，超出 `[0, 1]` 的部分被截断
+pub fn encode(color: Vec3, space: ColorSpace) -> BGRA8 {
+    match space {
+        ColorSpace::Linear => to_bgra(color),
+        ColorSpace::Srgb => {
+            let c = color.clamp(Vec3::ZERO, Vec3::ONE);
+            to_bgra(vec3(
+                linear_to_srgb(c.x),
+                linear_to_srgb(c.y),
+                linear_to_srgb(c.z),
+            ))
+        }
+    }
+}
+
 pub fn to_vec3(color: BGRA8) -> Vec3 {
     vec3(
         color.b as f32 * 255.,
@@ -22,3 +78,30 @@ pub fn to_vec3(color: BGRA8) -> Vec3 {
         color.r as f32 * 255.,
     )
 }
+
+/// 色调映射，将线性的 HDR 颜色压缩到 `[0, 1]` 的显示范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneMapping {
+    /// 直接截断超出范围的部分
+    #[default]
+    Clamp,
+    /// `c / (1 + c)`
+    Reinhard,
+    /// ACES 电影色调曲线，采用 Krzysztof Narkowicz 的拟合
+    Aces,
+}
+
+impl ToneMapping {
+    pub fn apply(self, color: Vec3) -> Vec3 {
+        let color = color.max(Vec3::ZERO);
+        let mapped = match self {
+            ToneMapping::Clamp => color,
+            ToneMapping::Reinhard => color / (color + 1.),
+            ToneMapping::Aces => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                color * (a * color + b) / (color * (c * color + d) + e)
+            }
+        };
+        mapped.min(Vec3::ONE)
+    }
+}
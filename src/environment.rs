@@ -0,0 +1,375 @@
+use std::{f32::consts::PI, fmt, fs::File, io::BufReader, path::Path, sync::OnceLock};
+
+use anyhow::{Context, Result};
+use glam::{vec2, vec3, Vec2, Vec3};
+use image::{codecs::hdr::HdrDecoder, DynamicImage, Rgb32FImage};
+use rayon::prelude::*;
+
+use crate::{
+    color::{self, ColorSpace},
+    shaders::distribution_ggx,
+};
+
+/// 预过滤的镜面反射贴图的级数，第 `i` 级对应粗糙度 `i / (SPECULAR_LEVELS - 1)`
+const SPECULAR_LEVELS: usize = 6;
+/// 第 1 级镜面反射贴图的最大宽度，之后每级减半。第 0 级为原图
+const SPECULAR_WIDTH: usize = 256;
+/// 预过滤时每个纹素的采样数
+const PREFILTER_SAMPLES: u32 = 64;
+/// 投影到球谐函数前，先把环境贴图缩小到不超过该宽度
+const SH_WIDTH: usize = 64;
+/// 镜面反射 BRDF 积分表的边长
+const BRDF_LUT_SIZE: usize = 32;
+
+/// 等距柱状投影（经纬度）的环境贴图，以左上为原点逐行存放线性颜色（bgr 顺序）
+///
+/// 横向为经度，图像中央朝向 -z；纵向为纬度，最上方为 +y
+#[derive(Debug)]
+struct EquirectMap {
+    width: usize,
+    height: usize,
+    data: Vec<Vec3>,
+}
+
+impl EquirectMap {
+    #[inline]
+    fn texel(&self, x: usize, y: usize) -> Vec3 {
+        self.data[y * self.width + x]
+    }
+
+    /// 纹素 `(x, y)` 中心对应的单位方向
+    fn direction(&self, x: usize, y: usize) -> Vec3 {
+        let phi = ((x as f32 + 0.5) / self.width as f32 - 0.5) * 2. * PI;
+        let theta = (y as f32 + 0.5) / self.height as f32 * PI;
+        vec3(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        )
+    }
+
+    /// 沿 `dir` 方向双线性插值，经度方向循环，纬度方向取边缘
+    fn sample(&self, dir: Vec3) -> Vec3 {
+        let dir = dir.normalize();
+        let u = 0.5 + dir.x.atan2(-dir.z) / (2. * PI);
+        let v = dir.y.clamp(-1., 1.).acos() / PI;
+        let x = u * self.width as f32 - 0.5;
+        let y = (v * self.height as f32 - 0.5).clamp(0., (self.height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let w = self.width as i64;
+        let xs = [x0 as i64, x0 as i64 + 1].map(|x| x.rem_euclid(w) as usize);
+        let y0 = y0 as usize;
+        let y1 = (y0 + 1).min(self.height - 1);
+        let top = self.texel(xs[0], y0).lerp(self.texel(xs[1], y0), fx);
+        let bottom = self.texel(xs[0], y1).lerp(self.texel(xs[1], y1), fx);
+        top.lerp(bottom, fy)
+    }
+
+    /// 长宽各缩小一半，对 2x2 个纹素取平均
+    fn downsample(&self) -> Self {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = ((2 * x).min(self.width - 1), (2 * y).min(self.height - 1));
+                let (sx1, sy1) = ((sx + 1).min(self.width - 1), (sy + 1).min(self.height - 1));
+                let sum = self.texel(sx, sy)
+                    + self.texel(sx1, sy)
+                    + self.texel(sx, sy1)
+                    + self.texel(sx1, sy1);
+                data.push(sum * 0.25);
+            }
+        }
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+}
+
+/// 用于基于图像的光照（IBL）的环境
+///
+/// 创建时预先计算漫反射辐照度（3 阶球谐函数）和按粗糙度预过滤的镜面反射贴图
+pub struct Environment {
+    /// 按粗糙度由小到大预过滤的镜面反射贴图，第 0 级即原图
+    specular: Vec<EquirectMap>,
+    /// 辐射度在 9 个实球谐基函数上的投影
+    sh: [Vec3; 9],
+}
+
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let base = &self.specular[0];
+        f.debug_struct("Environment")
+            .field("width", &base.width)
+            .field("height", &base.height)
+            .field("sh", &self.sh)
+            .finish()
+    }
+}
+
+impl Environment {
+    /// 读取等距柱状投影的环境贴图。Radiance `.hdr` 按线性辐射度读取，PNG 等按 sRGB 解码
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let context = || format!("无法加载环境贴图 {}", path.display());
+        let is_hdr = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("hdr"));
+        let img = if is_hdr {
+            // image::open 会把 .hdr 转换为 8 位图像，丢失超出 1 的部分，所以直接用解码器读取浮点数据
+            let decoder = HdrDecoder::new(BufReader::new(File::open(path).with_context(context)?))
+                .with_context(context)?;
+            let meta = decoder.metadata();
+            let pixels = decoder.read_image_hdr().with_context(context)?;
+            let raw = pixels.iter().flat_map(|p| p.0).collect();
+            DynamicImage::ImageRgb32F(
+                Rgb32FImage::from_raw(meta.width, meta.height, raw).with_context(context)?,
+            )
+        } else {
+            image::open(path).with_context(context)?
+        };
+        Ok(Self::from_image(img))
+    }
+
+    /// 浮点图像视为线性辐射度，8 位图像视为 sRGB 编码的颜色
+    pub fn from_image(img: DynamicImage) -> Self {
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let data = match img {
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => img
+                .to_rgb32f()
+                .pixels()
+                .map(|p| vec3(p.0[2], p.0[1], p.0[0]))
+                .collect(),
+            _ => img
+                .to_rgb8()
+                .pixels()
+                .map(|p| {
+                    let decode = |c| color::decode_u8(c, ColorSpace::Srgb);
+                    vec3(decode(p.0[2]), decode(p.0[1]), decode(p.0[0]))
+                })
+                .collect(),
+        };
+        // 第 0 级为原图，之后每级长宽减半，预过滤时按采样的立体角选择级别
+        let mut mips = vec![EquirectMap {
+            width,
+            height,
+            data,
+        }];
+        while let Some(last) = mips.last().filter(|m| m.width > 1 || m.height > 1) {
+            let next = last.downsample();
+            mips.push(next);
+        }
+        let sh = project_sh(&mips);
+        let filtered = prefilter_specular(&mips);
+        let specular = mips.into_iter().take(1).chain(filtered).collect();
+        Self { specular, sh }
+    }
+
+    /// `dir` 方向上的辐射度，用于绘制天空盒
+    pub fn radiance(&self, dir: Vec3) -> Vec3 {
+        self.specular[0].sample(dir)
+    }
+
+    /// 法线为 `normal` 的表面接收到的辐照度，Lambert 表面的出射辐射度为 `albedo / π * irradiance`
+    pub fn irradiance(&self, normal: Vec3) -> Vec3 {
+        // 余弦卷积在各阶上的系数
+        const A: [f32; 3] = [PI, 2. * PI / 3., PI / 4.];
+        let y = sh_basis(normal.normalize());
+        let mut e = Vec3::ZERO;
+        for (k, (c, y)) in self.sh.iter().zip(y).enumerate() {
+            let l = match k {
+                0 => 0,
+                1..=3 => 1,
+                _ => 2,
+            };
+            e += *c * (A[l] * y);
+        }
+        e.max(Vec3::ZERO)
+    }
+
+    /// 沿反射方向 `dir` 按粗糙度预过滤的辐射度，在相邻两级之间线性插值
+    pub fn specular(&self, dir: Vec3, roughness: f32) -> Vec3 {
+        let lod = roughness.clamp(0., 1.) * (SPECULAR_LEVELS - 1) as f32;
+        let lower = lod.floor() as usize;
+        let upper = (lower + 1).min(SPECULAR_LEVELS - 1);
+        let c = self.specular[lower].sample(dir);
+        let t = lod - lower as f32;
+        if t > 0. {
+            c.lerp(self.specular[upper].sample(dir), t)
+        } else {
+            c
+        }
+    }
+}
+
+/// 按立体角在 mipmap 之间三线性插值
+fn sample_lod(mips: &[EquirectMap], dir: Vec3, lod: f32) -> Vec3 {
+    let lod = lod.clamp(0., (mips.len() - 1) as f32);
+    let lower = lod.floor() as usize;
+    let upper = (lower + 1).min(mips.len() - 1);
+    mips[lower]
+        .sample(dir)
+        .lerp(mips[upper].sample(dir), lod - lower as f32)
+}
+
+/// 假设视线、法线与反射方向重合，对 GGX 分布做重要性采样，得到第 1 级及之后各级粗糙度的镜面反射贴图
+fn prefilter_specular(mips: &[EquirectMap]) -> Vec<EquirectMap> {
+    let base = &mips[0];
+    // 原图一个纹素的平均立体角
+    let texel_solid_angle = 4. * PI / (base.width * base.height) as f32;
+    let mut levels = Vec::with_capacity(SPECULAR_LEVELS - 1);
+    for i in 1..SPECULAR_LEVELS {
+        let roughness = i as f32 / (SPECULAR_LEVELS - 1) as f32;
+        let width = (base.width.min(SPECULAR_WIDTH) >> (i - 1)).max(1);
+        let height = (base.height * width / base.width).max(1);
+        let mut level = EquirectMap {
+            width,
+            height,
+            data: vec![Vec3::ZERO; width * height],
+        };
+        let directions: Vec<Vec3> = (0..width * height)
+            .map(|i| level.direction(i % width, i / width))
+            .collect();
+        level
+            .data
+            .par_iter_mut()
+            .zip(directions)
+            .for_each(|(texel, n)| {
+                let (mut sum, mut weight) = (Vec3::ZERO, 0.);
+                for s in 0..PREFILTER_SAMPLES {
+                    let h = importance_sample_ggx(hammersley(s, PREFILTER_SAMPLES), n, roughness);
+                    let n_dot_h = n.dot(h);
+                    let l = 2. * n_dot_h * h - n;
+                    let n_dot_l = n.dot(l);
+                    if n_dot_l <= 0. {
+                        continue;
+                    }
+                    // 由采样的概率密度估计每个采样覆盖的立体角，从而选择合适的 mipmap 级别，
+                    // 避免亮点在粗糙的级别上形成噪点
+                    let pdf = distribution_ggx(n_dot_h, roughness) / 4.;
+                    let sample_solid_angle = 1. / (PREFILTER_SAMPLES as f32 * pdf + 1e-4);
+                    let lod = 0.5 * (sample_solid_angle / texel_solid_angle).log2() + 1.;
+                    sum += sample_lod(mips, l, lod) * n_dot_l;
+                    weight += n_dot_l;
+                }
+                if weight > 0. {
+                    *texel = sum / weight;
+                }
+            });
+        levels.push(level);
+    }
+    levels
+}
+
+/// 将辐射度投影到前 3 阶的实球谐基函数上
+fn project_sh(mips: &[EquirectMap]) -> [Vec3; 9] {
+    let map = mips
+        .iter()
+        .find(|m| m.width <= SH_WIDTH)
+        .unwrap_or(&mips[mips.len() - 1]);
+    let mut sh = [Vec3::ZERO; 9];
+    let d_phi = 2. * PI / map.width as f32;
+    let d_theta = PI / map.height as f32;
+    for y in 0..map.height {
+        let theta = (y as f32 + 0.5) * d_theta;
+        let solid_angle = d_phi * d_theta * theta.sin();
+        for x in 0..map.width {
+            let radiance = map.texel(x, y) * solid_angle;
+            for (c, y) in sh.iter_mut().zip(sh_basis(map.direction(x, y))) {
+                *c += radiance * y;
+            }
+        }
+    }
+    sh
+}
+
+/// 前 3 阶的实球谐基函数在单位向量 `d` 处的值
+fn sh_basis(d: Vec3) -> [f32; 9] {
+    let Vec3 { x, y, z } = d;
+    [
+        0.282_095,
+        0.488_603 * y,
+        0.488_603 * z,
+        0.488_603 * x,
+        1.092_548 * x * y,
+        1.092_548 * y * z,
+        0.315_392 * (3. * z * z - 1.),
+        1.092_548 * x * z,
+        0.546_274 * (x * x - y * y),
+    ]
+}
+
+/// Hammersley 低差异序列的第 `i` 个点
+#[inline]
+fn hammersley(i: u32, n: u32) -> Vec2 {
+    vec2(
+        i as f32 / n as f32,
+        i.reverse_bits() as f32 * 2.328_306_4e-10,
+    )
+}
+
+/// 按 GGX 分布对半程向量做重要性采样，返回以 `n` 为天顶的世界坐标向量
+fn importance_sample_ggx(xi: Vec2, n: Vec3, roughness: f32) -> Vec3 {
+    let a = roughness * roughness;
+    let phi = 2. * PI * xi.x;
+    let cos_theta = ((1. - xi.y) / (1. + (a * a - 1.) * xi.y)).sqrt();
+    let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+    let up = if n.z.abs() < 0.999 { Vec3::Z } else { Vec3::X };
+    let t = up.cross(n).normalize();
+    let b = n.cross(t);
+    (t * (phi.cos() * sin_theta) + b * (phi.sin() * sin_theta) + n * cos_theta).normalize()
+}
+
+/// 分离求和近似中镜面反射 BRDF 的积分，返回 `(a, b)`，环境的镜面反射需乘以 `f0 * a + b`
+pub(crate) fn env_brdf(n_dot_v: f32, roughness: f32) -> Vec2 {
+    static LUT: OnceLock<Vec<Vec2>> = OnceLock::new();
+    let lut = LUT.get_or_init(|| {
+        (0..BRDF_LUT_SIZE * BRDF_LUT_SIZE)
+            .map(|i| {
+                let n_dot_v = (i % BRDF_LUT_SIZE) as f32 / (BRDF_LUT_SIZE - 1) as f32;
+                let roughness = (i / BRDF_LUT_SIZE) as f32 / (BRDF_LUT_SIZE - 1) as f32;
+                integrate_brdf(n_dot_v.max(1e-3), roughness)
+            })
+            .collect()
+    });
+    let scale = (BRDF_LUT_SIZE - 1) as f32;
+    let (x, y) = (
+        n_dot_v.clamp(0., 1.) * scale,
+        roughness.clamp(0., 1.) * scale,
+    );
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = (
+        (x0 + 1).min(BRDF_LUT_SIZE - 1),
+        (y0 + 1).min(BRDF_LUT_SIZE - 1),
+    );
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let at = |x: usize, y: usize| lut[y * BRDF_LUT_SIZE + x];
+    let top = at(x0, y0).lerp(at(x1, y0), fx);
+    let bottom = at(x0, y1).lerp(at(x1, y1), fx);
+    top.lerp(bottom, fy)
+}
+
+fn integrate_brdf(n_dot_v: f32, roughness: f32) -> Vec2 {
+    const SAMPLES: u32 = 256;
+    let v = vec3((1. - n_dot_v * n_dot_v).sqrt(), 0., n_dot_v);
+    let n = Vec3::Z;
+    // IBL 时 Smith 几何遮蔽函数取 k = r^2 / 2
+    let k = roughness * roughness / 2.;
+    let g1 = |n_dot_x: f32| n_dot_x / (n_dot_x * (1. - k) + k);
+    let mut sum = Vec2::ZERO;
+    for i in 0..SAMPLES {
+        let h = importance_sample_ggx(hammersley(i, SAMPLES), n, roughness);
+        let l = 2. * v.dot(h) * h - v;
+        let (n_dot_l, n_dot_h, v_dot_h) = (l.z, h.z.max(1e-4), v.dot(h).max(0.));
+        if n_dot_l > 0. {
+            let g_vis = g1(n_dot_v) * g1(n_dot_l) * v_dot_h / (n_dot_h * n_dot_v);
+            let fc = (1. - v_dot_h).powi(5);
+            sum += vec2((1. - fc) * g_vis, fc * g_vis);
+        }
+    }
+    sum / SAMPLES as f32
+}
@@ -1,7 +1,11 @@
+mod abuffer;
+pub mod clip;
 pub mod color;
+pub mod environment;
 pub mod object;
 pub mod rasterizer;
 pub mod shaders;
+pub mod shadow;
 pub mod texture;
 pub mod transform;
 pub mod triangle;
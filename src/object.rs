@@ -1,29 +1,174 @@
-use glam::{vec3, Mat4, Vec2, Vec3};
-use image::io::Reader;
-use std::{fmt, path::Path};
+use glam::{vec3, Mat4, Vec2, Vec3, Vec4};
+use image::{io::Reader, DynamicImage};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 #[derive(Debug)]
 pub struct Object {
     pub vertices: Vec<Vec3>,
+    /// 与 `vertices` 一一对应，模型没有顶点颜色时为空
     pub vertex_color: Vec<Vec3>,
     pub normals: Vec<Vec3>,
+    /// 模型没有纹理坐标时为空，`texcoord_indices` 也为空
     pub texcoords: Vec<Vec2>,
     pub indices: Vec<[usize; 3]>,
     pub normal_indices: Vec<[usize; 3]>,
     pub texcoord_indices: Vec<[usize; 3]>,
+    /// 切线，w 为 ±1，副切线为 `w * normal.cross(tangent)`，与 MikkTSpace 的约定相同。
+    /// 没有纹理坐标时为空，`tangent_indices` 也为空
+    pub tangents: Vec<Vec4>,
+    pub tangent_indices: Vec<[usize; 3]>,
     pub model: Mat4,
-    pub texture: Texture,
+    /// 没有纹理坐标时即使设置了纹理也不会被采样
+    pub texture: Option<Texture>,
+    /// 从 .mtl 中读取的材质，多个网格可以共用同一个材质
+    pub material: Option<Arc<Material>>,
 }
 
-use anyhow::Result;
+/// .mtl 文件中的材质，颜色均为线性值并按 bgr 排列
+#[derive(Debug)]
+pub struct Material {
+    pub name: String,
+    /// 环境光系数（Ka）
+    pub ambient: Vec3,
+    /// 漫反射系数（Kd）
+    pub diffuse: Vec3,
+    /// 高光系数（Ks）
+    pub specular: Vec3,
+    /// 高光指数（Ns）
+    pub shininess: f32,
+    /// 不透明度（d）
+    pub dissolve: f32,
+    /// 高光贴图（map_Ks），与 `specular` 相乘
+    pub specular_map: Option<Texture>,
+    /// 法线贴图（map_Bump、bump 或 norm），按线性数据读取
+    pub normal_map: Option<Texture>,
+}
+
+use anyhow::{Context, Result};
 
 use crate::texture::Texture;
 
+/// 模型没有顶点颜色时使用的颜色
+pub const DEFAULT_COLOR: Vec3 = Vec3::new(0.361, 0.4745, 0.5804);
+
+/// 生成法线的方式
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum NormalMode {
+    /// 每个三角形使用自己的面法线
+    #[default]
+    Flat,
+    /// 位置相同的顶点按各相邻三角形在该处的内角加权平均面法线。
+    /// 两个面的法线夹角超过 `crease_angle`（角度）时互不平均，保持锐利的棱
+    Smooth { crease_angle: f32 },
+}
+
+/// 读取 .obj 文件的选项
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LoadOptions {
+    /// 文件中没有法线时如何生成
+    pub normals: NormalMode,
+}
+
 impl Object {
-    /// 从 .obj 文件中读取对象模型
-    pub fn load_obj<P: AsRef<Path> + fmt::Debug>(obj_path: P, texture_path: P) -> Result<Object> {
-        let (models, _) = tobj::load_obj(obj_path, &tobj::LoadOptions::default())?;
-        let mesh = &models[0].mesh;
+    /// 从 .obj 文件中读取第一个网格，不带纹理，可以再用 [`Object::texture`] 设置
+    pub fn load_obj<P: AsRef<Path>>(obj_path: P) -> Result<Object> {
+        Self::load_obj_with(obj_path, &LoadOptions::default())
+    }
+
+    /// 同 [`Object::load_obj`]，可以指定读取选项
+    pub fn load_obj_with<P: AsRef<Path>>(obj_path: P, options: &LoadOptions) -> Result<Object> {
+        let obj_path = obj_path.as_ref();
+        let (models, _) = tobj::load_obj(obj_path, &tobj_options())
+            .with_context(|| format!("无法加载模型 {}", obj_path.display()))?;
+        let model = models
+            .first()
+            .with_context(|| format!("{} 中没有网格", obj_path.display()))?;
+        Ok(Self::from_mesh(&model.mesh, None, options))
+    }
+
+    /// 读取 .obj 文件中的所有网格，每个网格为一个对象
+    ///
+    /// 同时读取 .obj 引用的 .mtl 材质，贴图路径相对于 .obj 所在的目录。
+    /// 漫反射贴图（map_Kd）作为对象的纹理，没有漫反射贴图时颜色完全由 Kd 决定
+    ///
+    /// 读取 .mtl 失败时网格照常读取但都不带材质，失败的原因作为第二个返回值，由调用者决定如何提示
+    pub fn load_all<P: AsRef<Path>>(obj_path: P) -> Result<(Vec<Object>, Option<anyhow::Error>)> {
+        Self::load_all_with(obj_path, &LoadOptions::default())
+    }
+
+    /// 同 [`Object::load_all`]，可以指定读取选项
+    pub fn load_all_with<P: AsRef<Path>>(
+        obj_path: P,
+        options: &LoadOptions,
+    ) -> Result<(Vec<Object>, Option<anyhow::Error>)> {
+        let obj_path = obj_path.as_ref();
+        let (models, materials) = tobj::load_obj(obj_path, &tobj_options())
+            .with_context(|| format!("无法加载模型 {}", obj_path.display()))?;
+        let (materials, material_error) = match materials {
+            Ok(materials) => (materials, None),
+            Err(e) => {
+                let e = anyhow::Error::new(e)
+                    .context(format!("无法加载 {} 引用的材质", obj_path.display()));
+                (Vec::new(), Some(e))
+            }
+        };
+        let dir = obj_path.parent().unwrap_or(Path::new(""));
+        // 同一张贴图只解码一次
+        let mut images: HashMap<PathBuf, DynamicImage> = HashMap::new();
+        let mut load_image = |name: &str| -> Result<Option<DynamicImage>> {
+            let name = texture_file_name(name);
+            if name.is_empty() {
+                return Ok(None);
+            }
+            let path = dir.join(name);
+            if !images.contains_key(&path) {
+                let context = || format!("无法加载贴图 {}", path.display());
+                let img = Reader::open(&path)
+                    .with_context(context)?
+                    .decode()
+                    .with_context(context)?;
+                images.insert(path.clone(), img);
+            }
+            Ok(Some(images[&path].clone()))
+        };
+        let mut loaded = Vec::with_capacity(materials.len());
+        for m in &materials {
+            let bgr = |c: [f32; 3]| vec3(c[2], c[1], c[0]);
+            let normal_map = match m.unknown_param.get("norm") {
+                Some(name) if m.normal_texture.is_empty() => load_image(name)?,
+                _ => load_image(&m.normal_texture)?,
+            };
+            let diffuse_map = load_image(&m.diffuse_texture)?.map(Texture::new);
+            let material = Material {
+                name: m.name.clone(),
+                ambient: bgr(m.ambient),
+                diffuse: bgr(m.diffuse),
+                specular: bgr(m.specular),
+                shininess: m.shininess,
+                dissolve: m.dissolve,
+                specular_map: load_image(&m.specular_texture)?.map(Texture::new),
+                normal_map: normal_map.map(Texture::linear),
+            };
+            loaded.push((Arc::new(material), diffuse_map));
+        }
+        let objects = models
+            .iter()
+            .map(|model| {
+                let loaded = model.mesh.material_id.and_then(|id| loaded.get(id));
+                let texture = loaded.and_then(|(_, texture)| texture.clone());
+                let mut object = Self::from_mesh(&model.mesh, texture, options);
+                object.material = loaded.map(|(material, _)| material.clone());
+                object
+            })
+            .collect();
+        Ok((objects, material_error))
+    }
+
+    fn from_mesh(mesh: &tobj::Mesh, texture: Option<Texture>, options: &LoadOptions) -> Object {
         let mut vertices = Vec::with_capacity(mesh.positions.len() / 3);
         let mut vertex_color = Vec::with_capacity(mesh.vertex_color.len() / 3);
         let mut normals = Vec::with_capacity(mesh.normals.len() / 3);
@@ -37,15 +182,13 @@ impl Object {
                 mesh.positions[3 * i + 1],
                 mesh.positions[3 * i + 2],
             ));
-            vertex_color.push(if 3 * i + 2 < mesh.vertex_color.len() {
-                vec3(
-                    mesh.vertex_color[3 * i],
-                    mesh.vertex_color[3 * i + 1],
-                    mesh.vertex_color[3 * i + 2],
-                )
-            } else {
-                vec3(0.361, 0.4745, 0.5804)
-            });
+        }
+        for i in 0..mesh.vertex_color.len() / 3 {
+            vertex_color.push(vec3(
+                mesh.vertex_color[3 * i],
+                mesh.vertex_color[3 * i + 1],
+                mesh.vertex_color[3 * i + 2],
+            ));
         }
         for i in 0..mesh.normals.len() / 3 {
             normals.push(
@@ -81,17 +224,7 @@ impl Object {
                 mesh.texcoord_indices[3 * i + 2] as usize,
             ]);
         }
-        // 生成的模型中不包含法向量，则自动根据三个顶点为其生成
-        if normals.is_empty() {
-            for [i, j, k] in indices.iter().copied() {
-                let va = vertices[i] - vertices[j];
-                let vb = vertices[k] - vertices[j];
-                normal_indices.push([normals.len(), normals.len(), normals.len()]);
-                normals.push(va.cross(vb).normalize());
-            }
-        }
-        let spot_texture = Reader::open(texture_path)?.decode()?;
-        Ok(Object {
+        let mut object = Object {
             vertices,
             vertex_color,
             normals,
@@ -99,12 +232,219 @@ impl Object {
             indices,
             normal_indices,
             texcoord_indices,
+            tangents: Vec::new(),
+            tangent_indices: Vec::new(),
             model: Default::default(),
-            texture: Texture::new(spot_texture),
-        })
+            texture,
+            material: None,
+        };
+        // 生成的模型中不包含法向量，则自动根据三个顶点为其生成
+        if object.normals.is_empty() {
+            object.generate_normals(options.normals);
+        } else {
+            object.generate_tangents();
+        }
+        object
+    }
+
+    /// 丢弃已有的法线，根据顶点位置重新生成，切线也随之重新生成
+    pub fn generate_normals(&mut self, mode: NormalMode) {
+        let face_normals: Vec<Vec3> = self
+            .indices
+            .iter()
+            .map(|&[i, j, k]| {
+                // 逆时针为正面，与背面剔除一致
+                let va = self.vertices[j] - self.vertices[i];
+                let vb = self.vertices[k] - self.vertices[i];
+                va.cross(vb).normalize_or_zero()
+            })
+            .collect();
+        self.normals.clear();
+        self.normal_indices.clear();
+        match mode {
+            NormalMode::Flat => {
+                for &n in &face_normals {
+                    let id = self.normals.len();
+                    self.normal_indices.push([id, id, id]);
+                    self.normals.push(n);
+                }
+            }
+            NormalMode::Smooth { crease_angle } => self.smooth_normals(&face_normals, crease_angle),
+        }
+        self.generate_tangents();
+    }
+
+    /// 按折痕角平均相邻三角形的面法线
+    fn smooth_normals(&mut self, face_normals: &[Vec3], crease_angle: f32) {
+        // 位置完全相同的顶点视为同一个，得到每个位置相邻的三角形
+        let mut welded: HashMap<[u32; 3], usize> = HashMap::new();
+        let position_ids: Vec<usize> = self
+            .vertices
+            .iter()
+            .map(|v| {
+                let next = welded.len();
+                *welded.entry(v.to_array().map(f32::to_bits)).or_insert(next)
+            })
+            .collect();
+        let mut adjacent = vec![Vec::new(); welded.len()];
+        for (f, face) in self.indices.iter().enumerate() {
+            let angles = corner_angles(face.map(|i| self.vertices[i]));
+            for (v, weight) in face.iter().zip(angles) {
+                if weight > 0. && face_normals[f] != Vec3::ZERO {
+                    adjacent[position_ids[*v]].push((f, weight));
+                }
+            }
+        }
+
+        let cos_crease = crease_angle.to_radians().cos();
+        // 同一位置上平均结果相同的角共用一条法线
+        let mut shared: HashMap<(usize, [u32; 3]), usize> = HashMap::new();
+        for (f, &face) in self.indices.iter().enumerate() {
+            let n_f = face_normals[f];
+            let ids = face.map(|v| {
+                let p = position_ids[v];
+                let n = adjacent[p]
+                    .iter()
+                    .filter(|&&(g, _)| n_f.dot(face_normals[g]) >= cos_crease)
+                    .map(|&(g, weight)| face_normals[g] * weight)
+                    .sum::<Vec3>()
+                    .normalize_or_zero();
+                // 退化三角形没有面法线，也不参与平均
+                let n = if n == Vec3::ZERO { n_f } else { n };
+                *shared
+                    .entry((p, n.to_array().map(f32::to_bits)))
+                    .or_insert_with(|| {
+                        self.normals.push(n);
+                        self.normals.len() - 1
+                    })
+            });
+            self.normal_indices.push(ids);
+        }
+    }
+
+    /// 根据位置、法线和纹理坐标生成切线，没有纹理坐标时清空切线
+    ///
+    /// 与 MikkTSpace 相同：切线沿纹理坐标 u 增大的方向，按各三角形在顶点处的内角加权平均，
+    /// 再对顶点法线正交化。位置、法线、纹理坐标都相同且手性一致的角共用一条切线
+    pub fn generate_tangents(&mut self) {
+        self.tangents.clear();
+        self.tangent_indices.clear();
+        if !self.has_texcoords() {
+            return;
+        }
+        // 每个角的 (切线, 副切线) 累加值
+        let mut sums: Vec<(Vec3, Vec3)> = Vec::new();
+        let mut shared: HashMap<([usize; 3], bool), usize> = HashMap::new();
+        for f in 0..self.indices.len() {
+            let corners = self.indices[f];
+            let [p0, p1, p2] = corners.map(|i| self.vertices[i]);
+            let [uv0, uv1, uv2] = self.texcoord_indices[f].map(|i| self.texcoords[i]);
+            let (e1, e2) = (p1 - p0, p2 - p0);
+            let (d1, d2) = (uv1 - uv0, uv2 - uv0);
+            let det = d1.x * d2.y - d2.x * d1.y;
+            // 纹理坐标退化的三角形不贡献切线
+            let (t, b) = if det.abs() > f32::EPSILON {
+                (
+                    ((e1 * d2.y - e2 * d1.y) / det).normalize_or_zero(),
+                    ((e2 * d1.x - e1 * d2.x) / det).normalize_or_zero(),
+                )
+            } else {
+                (Vec3::ZERO, Vec3::ZERO)
+            };
+            let angles = corner_angles([p0, p1, p2]);
+            let ids = [0, 1, 2].map(|c| {
+                let weight = angles[c];
+                let key = [
+                    corners[c],
+                    self.normal_indices[f][c],
+                    self.texcoord_indices[f][c],
+                ];
+                let id = *shared.entry((key, det < 0.)).or_insert_with(|| {
+                    sums.push((Vec3::ZERO, Vec3::ZERO));
+                    sums.len() - 1
+                });
+                sums[id].0 += t * weight;
+                sums[id].1 += b * weight;
+                id
+            });
+            self.tangent_indices.push(ids);
+        }
+        // 共用一条切线的角法线也相同，对该法线正交化
+        let mut normal_of = vec![0; sums.len()];
+        for ((key, _), &id) in &shared {
+            normal_of[id] = key[1];
+        }
+        self.tangents = sums
+            .iter()
+            .zip(normal_of)
+            .map(|(&(t, b), n)| {
+                let n = self.normals[n];
+                let t = (t - n * n.dot(t))
+                    .try_normalize()
+                    .unwrap_or_else(|| n.any_orthonormal_vector());
+                let w = if n.cross(t).dot(b) < 0. { -1. } else { 1. };
+                t.extend(w)
+            })
+            .collect();
     }
     pub fn model(mut self, model: Mat4) -> Self {
         self.model = model;
         self
     }
+    pub fn texture(mut self, texture: Texture) -> Self {
+        self.texture = Some(texture);
+        self
+    }
+    pub fn has_texcoords(&self) -> bool {
+        self.texcoord_indices.len() == self.indices.len()
+    }
+}
+
+/// 读取 .obj 时传给 tobj 的选项，多边形面统一三角化
+fn tobj_options() -> tobj::LoadOptions {
+    tobj::LoadOptions {
+        triangulate: true,
+        ..Default::default()
+    }
+}
+
+/// 去掉贴图语句中文件名前的选项（如 `-bm 0.5`、`-o 0 0 0`），文件名本身可以含有空格
+fn texture_file_name(statement: &str) -> &str {
+    /// 取出下一个以空白分隔的词，返回该词和剩余部分
+    fn next(s: &str) -> (&str, &str) {
+        let s = s.trim_start();
+        s.split_once(char::is_whitespace).unwrap_or((s, ""))
+    }
+    let mut rest = statement.trim();
+    loop {
+        let (option, after) = next(rest);
+        // 各选项的参数个数，-o、-s、-t 的后两个参数可以省略
+        let (min, max) = match option {
+            "-blendu" | "-blendv" | "-boost" | "-texres" | "-clamp" | "-bm" | "-imfchan"
+            | "-type" | "-cc" => (1, 1),
+            "-mm" => (2, 2),
+            "-o" | "-s" | "-t" => (1, 3),
+            _ => return rest.trim(),
+        };
+        rest = after;
+        for i in 0..max {
+            let (arg, after) = next(rest);
+            if i >= min && arg.parse::<f32>().is_err() {
+                break;
+            }
+            rest = after;
+        }
+    }
+}
+
+/// 三角形在三个顶点处的内角，退化的三角形为 0
+fn corner_angles([a, b, c]: [Vec3; 3]) -> [f32; 3] {
+    [(b - a, c - a), (c - b, a - b), (a - c, b - c)].map(|(u, v)| {
+        let angle = u.angle_between(v);
+        if angle.is_finite() {
+            angle
+        } else {
+            0.
+        }
+    })
 }
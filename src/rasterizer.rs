@@ -1,33 +1,235 @@
 use crate::{
-    color,
-    object::Object,
-    shaders::{Payload, Shader},
-    texture::Texture,
-    triangle::Triangle,
+    abuffer::FragmentTile,
+    clip,
+    color::{self, ColorSpace, ToneMapping},
+    environment::Environment,
+    object::{self, Object},
+    shaders::{Payload, Shader, Uniforms, Vertex},
+    triangle::{EdgeFunctions, Triangle},
 };
+use anyhow::Result;
 use glam::{Mat4, Vec2, Vec3, Vec4};
+use image::{ImageFormat, Rgb, RgbImage};
+use rayon::prelude::*;
 use rgb::alt::BGRA8;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// 三角形剔除模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CullMode {
+    /// 不剔除
+    #[default]
+    None,
+    /// 剔除背面
+    Back,
+    /// 剔除正面
+    Front,
+}
+
+/// 3D 光栅化的执行方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    /// 单线程逐个三角形光栅化，作为参考实现
+    Serial,
+    /// 按 tile 分箱后多线程并行光栅化，结果与 `Serial` 一致
+    #[default]
+    Tiled,
+}
+
+/// tile 的边长，以像素为单位
+const TILE_SIZE: usize = 32;
+
+/// 多重采样抗锯齿（MSAA）的采样数
+///
+/// 每个像素只着色一次，但覆盖和深度测试按各个采样点分别进行，最后对采样点求平均
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Msaa {
+    /// 不开启 MSAA，每个像素只在中心采样
+    #[default]
+    Off,
+    X2,
+    X4,
+    X8,
+}
+
+impl Msaa {
+    /// 每个像素的采样数
+    pub fn samples(self) -> usize {
+        self.pattern().len()
+    }
+    /// 采样点相对于像素中心的偏移，以 1/16 像素为单位，y 轴向上
+    ///
+    /// 采用 Direct3D 的标准采样模式（将其 y 轴翻转）
+    pub fn pattern(self) -> &'static [(i64, i64)] {
+        match self {
+            Msaa::Off => &[(0, 0)],
+            Msaa::X2 => &[(4, -4), (-4, 4)],
+            Msaa::X4 => &[(-2, 6), (6, 2), (-6, -2), (2, -6)],
+            Msaa::X8 => &[
+                (1, 3),
+                (-1, -3),
+                (5, -1),
+                (-3, 5),
+                (-5, -5),
+                (-7, 1),
+                (3, -7),
+                (7, 7),
+            ],
+        }
+    }
+}
+
+/// 超采样（SSAA）降采样时使用的重建滤波器
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResolveFilter {
+    /// 对像素内的所有子像素求平均
+    #[default]
+    Box,
+    /// 以像素中心为顶点、半径为一个像素的三角形（帐篷）权重，会略微混合相邻像素
+    Tent,
+}
+
+/// 正面三角形在屏幕上的顶点环绕方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrontFace {
+    /// 逆时针，与 .obj 的习惯一致
+    #[default]
+    Ccw,
+    /// 顺时针
+    Cw,
+}
+
+/// 半透明绘制时的混合方式，`src` 为着色器的输出，`dst` 为缓冲区中已有的颜色
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    /// `src * a + dst * (1 - a)`
+    #[default]
+    Alpha,
+    /// `dst + src * a`，适合火焰、光晕等发光效果
+    Additive,
+    /// `src + dst * (1 - a)`，要求着色器输出已经预乘 alpha 的颜色
+    Premultiplied,
+}
+
+impl BlendMode {
+    #[inline]
+    pub(crate) fn blend(self, src: Vec4, dst: Vec4) -> Vec4 {
+        let a = src.w;
+        let color = match self {
+            BlendMode::Alpha => src.truncate() * a + dst.truncate() * (1. - a),
+            BlendMode::Additive => dst.truncate() + src.truncate() * a,
+            BlendMode::Premultiplied => src.truncate() + dst.truncate() * (1. - a),
+        };
+        let alpha = match self {
+            BlendMode::Additive => (dst.w + a).min(1.),
+            _ => a + dst.w * (1. - a),
+        };
+        color.extend(alpha)
+    }
+}
+
+/// 半透明物体的绘制方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transparency {
+    /// 按物体和三角形的远近排序后依次混合，相互穿插的三角形会出错
+    #[default]
+    Sorted,
+    /// 用 A-buffer 保存每个采样点上的所有片元，全部绘制完后逐采样点排序再混合，结果与绘制顺序无关
+    ///
+    /// `budget` 为平均每个采样点最多保存的片元数。某个 tile 的片元超出预算后，
+    /// 之后的片元会直接与颜色缓冲混合，相当于被放到了已保存片元的后面
+    ABuffer { budget: usize },
+}
 
 pub struct Rasterizer<S> {
     width: usize,
     height: usize,
     frame_buf: Vec<BGRA8>,
+    /// 每个采样点 NDC 下的 z，近平面为 1，远平面为 -1，越大离相机越近
+    ///
+    /// 内部分辨率为屏幕的 `ssaa` 倍，每个内部像素占 `msaa.samples()` 个
     depth_buf: Vec<f32>,
+    /// 每个采样点的线性颜色，每次绘制结束后经过降采样和色调映射写入 `frame_buf`
+    color_buf: Vec<Vec4>,
+    msaa: Msaa,
+    /// SSAA 的倍数，内部以 `ssaa x ssaa` 倍的分辨率渲染，为 1 时不开启
+    ssaa: usize,
+    filter: ResolveFilter,
+    tone_mapping: ToneMapping,
+    /// 曝光补偿，以档 (EV) 为单位，颜色在色调映射前乘以 `2^exposure`
+    exposure: f32,
+    /// `frame_buf` 的色彩空间，设为 `ColorSpace::Linear` 时与旧版本一样不做 gamma 编码
+    output_color_space: ColorSpace,
     view: Mat4,
     projection: Mat4,
+    cull_mode: CullMode,
+    front_face: FrontFace,
+    render_mode: RenderMode,
+    blend_mode: BlendMode,
+    transparency: Transparency,
+    /// A-buffer 按 tile 划分的片元链表，只在 `Transparency::ABuffer` 下使用
+    fragment_tiles: Vec<FragmentTile>,
     pub shader: S,
 }
 
 // 实用函数
 impl<S: Shader> Rasterizer<S> {
     pub fn new(width: usize, height: usize, shader: S) -> Self {
+        Self::with_msaa(width, height, shader, Msaa::Off)
+    }
+
+    /// 创建开启 MSAA 的光栅化器
+    pub fn with_msaa(width: usize, height: usize, shader: S, msaa: Msaa) -> Self {
+        Self::with_anti_aliasing(width, height, shader, msaa, 1, Default::default())
+    }
+
+    /// 创建开启 SSAA 的光栅化器，内部以 `factor x factor` 倍的分辨率渲染，再用 `filter` 降采样
+    ///
+    /// 开销很大，主要用作对比其他抗锯齿方法的参考图像
+    pub fn with_ssaa(
+        width: usize,
+        height: usize,
+        shader: S,
+        factor: usize,
+        filter: ResolveFilter,
+    ) -> Self {
+        Self::with_anti_aliasing(width, height, shader, Msaa::Off, factor, filter)
+    }
+
+    fn with_anti_aliasing(
+        width: usize,
+        height: usize,
+        shader: S,
+        msaa: Msaa,
+        ssaa: usize,
+        filter: ResolveFilter,
+    ) -> Self {
+        assert!(ssaa >= 1, "SSAA 倍数至少为 1");
+        let samples = width * height * ssaa * ssaa * msaa.samples();
         Self {
             width,
             height,
             frame_buf: vec![Default::default(); width * height],
-            depth_buf: vec![f32::NEG_INFINITY; width * height],
+            depth_buf: vec![f32::NEG_INFINITY; samples],
+            color_buf: vec![Vec4::ZERO; samples],
+            msaa,
+            ssaa,
+            filter,
+            tone_mapping: Default::default(),
+            exposure: 0.,
+            output_color_space: Default::default(),
             view: Default::default(),
             projection: Default::default(),
+            cull_mode: Default::default(),
+            front_face: Default::default(),
+            render_mode: Default::default(),
+            blend_mode: Default::default(),
+            transparency: Default::default(),
+            fragment_tiles: Vec::new(),
             shader,
         }
     }
@@ -36,129 +238,617 @@ impl<S: Shader> Rasterizer<S> {
     pub fn clear(&mut self) {
         self.frame_buf.fill(Default::default());
         self.depth_buf.fill(f32::NEG_INFINITY);
+        self.color_buf.fill(Vec4::ZERO);
     }
 
     /// 获取内部 BGRA 数据
     #[inline]
     pub fn data(&self) -> &[u32] {
+        // BGRA8 与 u32 大小相同，按小端序解释即为 0xAARRGGBB
         unsafe {
-            std::slice::from_raw_parts(
-                std::mem::transmute(self.frame_buf.as_ptr()),
-                self.frame_buf.len() * 4,
-            )
+            std::slice::from_raw_parts(self.frame_buf.as_ptr().cast::<u32>(), self.frame_buf.len())
         }
     }
 
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
     #[inline]
     pub fn get_index(&self, x: usize, y: usize) -> usize {
         (self.height - 1 - y) * self.width + x
     }
+
+    /// 深度缓冲，只在未开启抗锯齿时与屏幕像素一一对应，用于生成阴影贴图
+    pub(crate) fn depth(&self) -> &[f32] {
+        &self.depth_buf
+    }
 }
 
 // 3D 光栅化
 impl<S: Shader> Rasterizer<S> {
+    /// 绘制不透明物体，通过深度测试的片元直接覆盖原有颜色并写入深度
     pub fn draw(&mut self, object: &Object) {
-        let vp = self.projection * self.view;
+        let triangles = self.assemble(object);
+        self.rasterize(&triangles, object, None);
+        self.resolve(&triangles);
+    }
+    /// 绘制半透明物体，应在所有不透明物体之后调用
+    ///
+    /// 片元仍做深度测试，但不写入深度，颜色按 [`BlendMode`] 与缓冲区混合，混合顺序见 [`Transparency`]
+    pub fn draw_transparent(&mut self, objects: &[&Object]) {
+        match self.transparency {
+            Transparency::Sorted => self.draw_sorted(objects),
+            Transparency::ABuffer { budget } => self.draw_abuffer(objects, budget),
+        }
+    }
+    /// 物体按离相机由远到近的顺序绘制，每个物体内的三角形也由远到近排序
+    ///
+    /// 排序以物体原点和三角形重心为准
+    fn draw_sorted(&mut self, objects: &[&Object]) {
+        // 相机坐标系下的 z，越小离相机越远
+        let view_z = |object: &&Object| (self.view * object.model).w_axis.z;
+        let mut objects = objects.to_vec();
+        objects.sort_by(|a, b| view_z(a).total_cmp(&view_z(b)));
+        for object in objects {
+            let mut triangles = self.assemble(object);
+            // 裁剪空间的 w 即相机坐标系下的 z
+            let depth = |t: &Triangle<S::Varyings>| t.v[0].w + t.v[1].w + t.v[2].w;
+            triangles.sort_by(|a, b| depth(a).total_cmp(&depth(b)));
+            self.rasterize(&triangles, object, Some(self.blend_mode));
+            self.resolve(&triangles);
+        }
+    }
+    /// 所有片元先存入 A-buffer，最后逐采样点排序混合
+    fn draw_abuffer(&mut self, objects: &[&Object], budget: usize) {
+        let (screen_width, screen_height) = (self.width * self.ssaa, self.height * self.ssaa);
+        let tiles_x = screen_width.div_ceil(TILE_SIZE);
+        let tiles_y = screen_height.div_ceil(TILE_SIZE);
+        let samples = self.msaa.samples();
+        if self.fragment_tiles.len() != tiles_x * tiles_y {
+            self.fragment_tiles = (0..tiles_x * tiles_y)
+                .map(|tile_id| {
+                    let width = TILE_SIZE.min(screen_width - tile_id % tiles_x * TILE_SIZE);
+                    let height = TILE_SIZE.min(screen_height - tile_id / tiles_x * TILE_SIZE);
+                    let capacity = budget * width * height * samples;
+                    FragmentTile::new(width, height, samples, capacity)
+                })
+                .collect();
+        }
+        for tile in &mut self.fragment_tiles {
+            tile.clear();
+        }
+
+        let mut drawn = Vec::new();
+        for object in objects {
+            let triangles = self.assemble(object);
+            self.rasterize(&triangles, object, Some(self.blend_mode));
+            drawn.extend(triangles);
+        }
+
+        let blend = self.blend_mode;
+        let mut scratch = Vec::new();
+        for (tile_id, tile) in self.fragment_tiles.iter().enumerate() {
+            let left = tile_id % tiles_x * TILE_SIZE;
+            let bottom = tile_id / tiles_x * TILE_SIZE;
+            for y in bottom..(bottom + TILE_SIZE).min(screen_height) {
+                for x in left..(left + TILE_SIZE).min(screen_width) {
+                    let index = ((screen_height - 1 - y) * screen_width + x) * samples;
+                    for s in 0..samples {
+                        let dst = &mut self.color_buf[index + s];
+                        *dst = tile.resolve(x - left, y - bottom, s, blend, *dst, &mut scratch);
+                    }
+                }
+            }
+        }
+        self.resolve(&drawn);
+    }
+    /// 在还没有被物体覆盖的采样点上绘制环境贴图作为背景，在不透明物体之前或之后调用均可
+    pub fn draw_skybox(&mut self, environment: &Environment) {
+        let (render_width, render_height) = (self.width * self.ssaa, self.height * self.ssaa);
+        let samples = self.msaa.samples();
+        // 屏幕上一点在近平面和远平面上的世界坐标之差即为视线方向
+        let inverse = (self.projection * self.view).inverse();
+        let direction = |x: f32, y: f32| {
+            let near = inverse * Vec4::new(x, y, 1., 1.);
+            let far = inverse * Vec4::new(x, y, -1., 1.);
+            far.truncate() / far.w - near.truncate() / near.w
+        };
+        let row = render_width * samples;
+        self.color_buf
+            .par_chunks_mut(row)
+            .zip(self.depth_buf.par_chunks(row))
+            .enumerate()
+            .for_each(|(i, (colors, depths))| {
+                let y = render_height - 1 - i;
+                let ndc_y = (y as f32 + 0.5) / render_height as f32 * 2. - 1.;
+                for x in 0..render_width {
+                    let pixel = x * samples..(x + 1) * samples;
+                    if depths[pixel.clone()]
+                        .iter()
+                        .all(|d| *d != f32::NEG_INFINITY)
+                    {
+                        continue;
+                    }
+                    let ndc_x = (x as f32 + 0.5) / render_width as f32 * 2. - 1.;
+                    let color = environment.radiance(direction(ndc_x, ndc_y)).extend(1.);
+                    for (c, d) in colors[pixel.clone()].iter_mut().zip(&depths[pixel]) {
+                        if *d == f32::NEG_INFINITY {
+                            *c = color;
+                        }
+                    }
+                }
+            });
+        let (right, top) = ((render_width - 1) as f32, (render_height - 1) as f32);
+        self.resolve_rect((0., top, right, 0.));
+    }
+    /// 上一次用 A-buffer 绘制半透明物体时，因超出预算而没能保存的片元数
+    pub fn fragment_overflow(&self) -> usize {
+        self.fragment_tiles.iter().map(|tile| tile.overflowed).sum()
+    }
+    /// 运行顶点着色器，裁剪并剔除三角形，返回顶点已变换到屏幕坐标的三角形
+    fn assemble(&self, object: &Object) -> Vec<Triangle<S::Varyings>> {
+        let uniforms = Uniforms {
+            model: object.model,
+            view: self.view,
+            projection: self.projection,
+            normal_matrix: object.model.inverse().transpose(),
+        };
+        let (render_width, render_height) = (self.width * self.ssaa, self.height * self.ssaa);
+        let mut triangles = Vec::with_capacity(object.indices.len());
+        let mut clipped = Vec::new();
+        let has_texcoords = object.has_texcoords();
+        let has_tangents = object.tangent_indices.len() == object.indices.len();
         for t_id in 0..object.indices.len() {
-            let [i, j, k] = object.indices[t_id];
-            let [ni, nj, nk] = object.normal_indices[t_id];
-            let [ti, tj, tk] = object.texcoord_indices[t_id];
-            let m_inv_t = object.model.inverse().transpose();
-            // 模型的世界坐标，一个客观的绝对坐标
-            let model_pos = [
-                object.model * object.vertices[i].extend(1.),
-                object.model * object.vertices[j].extend(1.),
-                object.model * object.vertices[k].extend(1.),
-            ];
-
-            // 在本应用的情况下，应当只需要考虑模型变换
-            let transform_normal = |n: Vec3| {
-                let n = n.extend(0.);
-                (m_inv_t * n).truncate()
-            };
-            let mut t = Triangle {
-                // 渲染的三角形坐标采取 mvp 变换后的 NDC 坐标
-                v: [vp * model_pos[0], vp * model_pos[1], vp * model_pos[2]],
-                color: [
-                    object.vertex_color[i],
-                    object.vertex_color[j],
-                    object.vertex_color[k],
-                ],
-                normal: [
-                    transform_normal(object.normals[ni]),
-                    transform_normal(object.normals[nj]),
-                    transform_normal(object.normals[nk]),
-                ],
-                texture: [
-                    object.texcoords[ti],
-                    object.texcoords[tj],
-                    object.texcoords[tk],
-                ],
+            let indices = object.indices[t_id];
+            let normal_indices = object.normal_indices[t_id];
+            let out = [0, 1, 2].map(|i| {
+                // 缺少的属性取默认值
+                let tex_coords = if has_texcoords {
+                    object.texcoords[object.texcoord_indices[t_id][i]]
+                } else {
+                    Vec2::ZERO
+                };
+                let tangent = if has_tangents {
+                    object.tangents[object.tangent_indices[t_id][i]]
+                } else {
+                    Vec4::ZERO
+                };
+                let vertex = Vertex {
+                    position: object.vertices[indices[i]],
+                    normal: object.normals[normal_indices[i]],
+                    tex_coords,
+                    color: object
+                        .vertex_color
+                        .get(indices[i])
+                        .copied()
+                        .unwrap_or(object::DEFAULT_COLOR),
+                    tangent,
+                };
+                self.shader.vertex(&vertex, &uniforms)
+            });
+            // 裁剪在齐次裁剪空间中进行，之后才做齐次除法
+            let t = Triangle {
+                v: out.map(|o| o.position),
+                varyings: out.map(|o| o.varyings),
             };
-            // 如果三角形有部分在视点之后，则放弃渲染，因为目前还没有做裁剪
-            if t.v.iter().any(|p| p.w >= 0.) {
-                continue;
+            // 裁剪掉视锥体外的部分，与视锥相交的三角形可能被切分为多个
+            clipped.clear();
+            clip::clip_triangle(&t, &mut clipped);
+            for mut t in clipped.drain(..) {
+                // 齐次除法将 (x,y,z) 限定在 [-1,1]
+                // 然后将 x、y 映射到屏幕坐标系上（开启 SSAA 时是放大后的内部分辨率）
+                // w 没有进行齐次除法，因为按之前的计算这里的 w 保存了 mv 变换之后的真实 z 值
+                for p in t.v.iter_mut() {
+                    p.x = 0.5 * render_width as f32 * (p.x / p.w + 1.);
+                    p.y = 0.5 * render_height as f32 * (p.y / p.w + 1.);
+                    p.z /= p.w;
+                }
+                if !self.culled(&t) {
+                    triangles.push(t);
+                }
+            }
+        }
+        triangles
+    }
+    /// 根据屏幕空间的有向面积判断三角形是否应被剔除
+    ///
+    /// 面积为 0（或因数值问题不是有限值）的退化三角形总是被剔除，避免计算重心坐标时除以 0
+    fn culled<V>(&self, t: &Triangle<V>) -> bool {
+        let area = t.signed_area();
+        if !area.is_finite() || area.abs() <= f32::EPSILON {
+            return true;
+        }
+        let front = (area > 0.) == (self.front_face == FrontFace::Ccw);
+        match self.cull_mode {
+            CullMode::None => false,
+            CullMode::Back => !front,
+            CullMode::Front => front,
+        }
+    }
+    /// 光栅化三角形，`blend` 为 `None` 时按不透明物体处理
+    fn rasterize(
+        &mut self,
+        triangles: &[Triangle<S::Varyings>],
+        object: &Object,
+        blend: Option<BlendMode>,
+    ) {
+        match self.render_mode {
+            RenderMode::Serial => self.rasterize_serial(triangles, object, blend),
+            RenderMode::Tiled => self.rasterize_tiled(triangles, object, blend),
+        }
+    }
+    /// 在单个线程中按顺序把所有三角形光栅化到整个屏幕上
+    fn rasterize_serial(
+        &mut self,
+        triangles: &[Triangle<S::Varyings>],
+        object: &Object,
+        blend: Option<BlendMode>,
+    ) {
+        let mut target = RenderTarget {
+            left: 0,
+            bottom: 0,
+            width: self.width * self.ssaa,
+            height: self.height * self.ssaa,
+            pattern: self.msaa.pattern(),
+            blend,
+            fragments: match (blend, self.transparency) {
+                (Some(_), Transparency::ABuffer { .. }) => Some(&mut self.fragment_tiles),
+                _ => None,
+            },
+            color_buf: &mut self.color_buf,
+            depth_buf: &mut self.depth_buf,
+        };
+        for t in triangles {
+            target.rasterize_triangle(t, &self.shader, object);
+        }
+    }
+    /// 先将三角形按包围盒分配到各个 tile，然后多个线程并行地光栅化各个 tile
+    ///
+    /// 每个 tile 内部仍按提交顺序处理三角形，因此结果与 [`Self::rasterize_serial`] 逐位一致
+    fn rasterize_tiled(
+        &mut self,
+        triangles: &[Triangle<S::Varyings>],
+        object: &Object,
+        blend: Option<BlendMode>,
+    ) {
+        let (screen_width, screen_height) = (self.width * self.ssaa, self.height * self.ssaa);
+        let tiles_x = screen_width.div_ceil(TILE_SIZE);
+        let tiles_y = screen_height.div_ceil(TILE_SIZE);
+        let mut bins = vec![Vec::new(); tiles_x * tiles_y];
+        for (t_id, t) in triangles.iter().enumerate() {
+            let (left, top, right, bottom) = t.bounding_box();
+            let (left, right) = (
+                (left as usize).min(screen_width - 1) / TILE_SIZE,
+                (right as usize).min(screen_width - 1) / TILE_SIZE,
+            );
+            let (bottom, top) = (
+                (bottom as usize).min(screen_height - 1) / TILE_SIZE,
+                (top as usize).min(screen_height - 1) / TILE_SIZE,
+            );
+            for ty in bottom..=top {
+                for tx in left..=right {
+                    bins[ty * tiles_x + tx].push(t_id);
+                }
             }
-            // 齐次除法将 (x,y,z) 限定在 [-1,1]
-            // 然后将 x、y 映射到屏幕坐标系上
-            // w 没有进行齐次除法，因为按之前的计算这里的 w 保存了 mv 变换之后的真实 z 值
-            for p in t.v.iter_mut() {
-                p.x = 0.5 * self.width as f32 * (p.x / p.w + 1.);
-                p.y = 0.5 * self.height as f32 * (p.y / p.w + 1.);
-                p.z /= p.w;
+        }
+
+        let pattern = self.msaa.pattern();
+        let samples = pattern.len();
+        // 屏幕上 (x, y) 所在行、从 x 开始的采样点下标
+        let row_index = |x: usize, y: usize| ((screen_height - 1 - y) * screen_width + x) * samples;
+        let shader = &self.shader;
+        let depth_buf = &mut self.depth_buf;
+        let color_buf = &mut self.color_buf;
+        // 开启 A-buffer 时，每个 tile 只访问自己的片元链表
+        let mut fragment_tiles: Vec<Option<&mut FragmentTile>> = match (blend, self.transparency) {
+            (Some(_), Transparency::ABuffer { .. }) => {
+                self.fragment_tiles.iter_mut().map(Some).collect()
+            }
+            _ => bins.iter().map(|_| None).collect(),
+        };
+        // 各 tile 先在自己的缓冲区中完成光栅化，结束后再写回屏幕
+        let tiles: Vec<_> = bins
+            .par_iter()
+            .zip(fragment_tiles.par_iter_mut())
+            .enumerate()
+            .filter(|(_, (bin, _))| !bin.is_empty())
+            .map(|(tile_id, (bin, fragments))| {
+                let left = tile_id % tiles_x * TILE_SIZE;
+                let bottom = tile_id / tiles_x * TILE_SIZE;
+                let width = TILE_SIZE.min(screen_width - left);
+                let height = TILE_SIZE.min(screen_height - bottom);
+                let mut tile_color = Vec::with_capacity(width * height * samples);
+                let mut tile_depth = Vec::with_capacity(width * height * samples);
+                for y in (bottom..bottom + height).rev() {
+                    let row = row_index(left, y);
+                    let row = row..row + width * samples;
+                    tile_color.extend_from_slice(&color_buf[row.clone()]);
+                    tile_depth.extend_from_slice(&depth_buf[row]);
+                }
+                let mut target = RenderTarget {
+                    left,
+                    bottom,
+                    width,
+                    height,
+                    pattern,
+                    blend,
+                    fragments: fragments.as_deref_mut().map(std::slice::from_mut),
+                    color_buf: &mut tile_color,
+                    depth_buf: &mut tile_depth,
+                };
+                for &t_id in bin {
+                    target.rasterize_triangle(&triangles[t_id], shader, object);
+                }
+                (left, bottom, width, height, tile_color, tile_depth)
+            })
+            .collect();
+
+        for (left, bottom, width, height, tile_color, tile_depth) in tiles {
+            let row_len = width * samples;
+            for (i, y) in (bottom..bottom + height).rev().enumerate() {
+                let row = row_index(left, y);
+                let tile_row = i * row_len..(i + 1) * row_len;
+                color_buf[row..row + row_len].copy_from_slice(&tile_color[tile_row.clone()]);
+                depth_buf[row..row + row_len].copy_from_slice(&tile_depth[tile_row]);
             }
-            self.rasterize_triangle(&t, &object.texture, &model_pos);
         }
     }
-    /// 将 3D 三角形光栅化到屏幕上。
+    /// 将三角形覆盖范围内的采样点降采样，经过色调映射后写入 `frame_buf`
+    ///
+    /// 先对每个内部像素的 MSAA 采样点求平均，再按 SSAA 的滤波器合并为屏幕像素
+    fn resolve<V>(&mut self, triangles: &[Triangle<V>]) {
+        if let Some(rect) = triangles
+            .iter()
+            .map(|t| t.bounding_box())
+            .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1), a.2.max(b.2), a.3.min(b.3)))
+        {
+            self.resolve_rect(rect);
+        }
+    }
+    /// 与 `resolve` 相同，范围为内部分辨率下的 `(left, top, right, bottom)`
+    fn resolve_rect(&mut self, (left, top, right, bottom): (f32, f32, f32, f32)) {
+        let factor = self.ssaa;
+        let samples = self.msaa.samples();
+        let render_width = self.width * factor;
+        let render_height = self.height * factor;
+        let (width, height) = (self.width, self.height);
+        let color_buf = &self.color_buf;
+        let frame_buf = &mut self.frame_buf;
+        // 内部像素 (x, y) 的平均颜色
+        let pixel = |x: usize, y: usize| {
+            let index = ((render_height - 1 - y) * render_width + x) * samples;
+            color_buf[index..index + samples].iter().sum::<Vec4>() / samples as f32
+        };
+        let (tone_mapping, exposure) = (self.tone_mapping, self.exposure.exp2());
+        let output_color_space = self.output_color_space;
+        // 帐篷滤波器会用到相邻像素，所以范围向外扩展一个像素
+        let margin = match self.filter {
+            ResolveFilter::Box => 0,
+            ResolveFilter::Tent => 1,
+        };
+        let (left, bottom) = (
+            (left as usize / factor).saturating_sub(margin),
+            (bottom as usize / factor).saturating_sub(margin),
+        );
+        let (right, top) = (
+            (right as usize / factor + margin).min(width - 1),
+            (top as usize / factor + margin).min(height - 1),
+        );
+        for y in bottom..=top {
+            for x in left..=right {
+                let color = match self.filter {
+                    ResolveFilter::Box => {
+                        let mut sum = Vec4::ZERO;
+                        for sy in y * factor..(y + 1) * factor {
+                            for sx in x * factor..(x + 1) * factor {
+                                sum += pixel(sx, sy);
+                            }
+                        }
+                        sum / (factor * factor) as f32
+                    }
+                    ResolveFilter::Tent => {
+                        // 以内部像素为单位，屏幕像素中心的坐标
+                        let center = |p: usize| (p as f32 + 0.5) * factor as f32;
+                        let weight = |p: usize, c: f32| {
+                            (1. - ((p as f32 + 0.5 - c) / factor as f32).abs()).max(0.)
+                        };
+                        let (cx, cy) = (center(x), center(y));
+                        let (mut sum, mut weights) = (Vec4::ZERO, 0.);
+                        for sy in (y * factor).saturating_sub(factor)
+                            ..((y + 2) * factor).min(render_height)
+                        {
+                            for sx in (x * factor).saturating_sub(factor)
+                                ..((x + 2) * factor).min(render_width)
+                            {
+                                let w = weight(sx, cx) * weight(sy, cy);
+                                sum += w * pixel(sx, sy);
+                                weights += w;
+                            }
+                        }
+                        sum / weights
+                    }
+                };
+                let mapped = tone_mapping.apply(color.truncate() * exposure);
+                frame_buf[(height - 1 - y) * width + x] = BGRA8 {
+                    a: (color.w.clamp(0., 1.) * 255. + 0.5) as u8,
+                    ..color::encode(mapped, output_color_space)
+                };
+            }
+        }
+    }
+}
+
+/// 光栅化的目标区域，可以是整个屏幕，也可以是屏幕上的一个 tile
+///
+/// 缓冲区只覆盖该区域，和屏幕一样按从上到下的行序存放
+struct RenderTarget<'a> {
+    /// 区域左下角在屏幕上的坐标
+    left: usize,
+    bottom: usize,
+    width: usize,
+    height: usize,
+    /// 采样点相对于像素中心的偏移，见 [`Msaa::pattern`]
+    pattern: &'static [(i64, i64)],
+    /// 半透明物体的混合方式，`None` 表示不透明物体
+    blend: Option<BlendMode>,
+    /// 开启 A-buffer 时，半透明片元保存到目标区域内各个 tile 的链表中，而不直接混合
+    fragments: Option<&'a mut [FragmentTile]>,
+    /// 每个像素依次存放各采样点的颜色和深度
+    color_buf: &'a mut [Vec4],
+    depth_buf: &'a mut [f32],
+}
+
+impl RenderTarget<'_> {
+    #[inline]
+    fn get_index(&self, x: usize, y: usize) -> usize {
+        (self.height - 1 - (y - self.bottom)) * self.width + (x - self.left)
+    }
+
+    /// 将 3D 三角形光栅化到目标区域内，区域外的部分被忽略。
     ///
     /// 注意 `t` 的 x y 坐标已经表示为屏幕坐标
-    fn rasterize_triangle(&mut self, t: &Triangle, texture: &Texture, model_pos: &[Vec4; 3]) {
+    fn rasterize_triangle<S: Shader>(
+        &mut self,
+        t: &Triangle<S::Varyings>,
+        shader: &S,
+        object: &Object,
+    ) {
         let bbox = t.bounding_box();
         let (left, top, right, bottom) = (
-            (bbox.0 as usize).min(self.width - 1),
-            (bbox.1 as usize).min(self.height - 1),
-            (bbox.2 as usize).min(self.width - 1),
-            (bbox.3 as usize).min(self.height - 1),
+            (bbox.0 as usize).max(self.left),
+            (bbox.1 as usize).min(self.bottom + self.height - 1),
+            (bbox.2 as usize).min(self.left + self.width - 1),
+            (bbox.3 as usize).max(self.bottom),
         );
+        // 顶点对齐到亚像素网格后退化为线或点，不覆盖任何像素
+        let Some(edges) = EdgeFunctions::new(t) else {
+            return;
+        };
+        let has_texcoords = object.has_texcoords();
+        let texture = object.texture.as_ref().filter(|_| has_texcoords);
+        let mut e_row = edges.evaluate(left, bottom);
+        let samples = self.pattern.len();
         for py in bottom..=top {
+            let mut e = e_row;
+            edges.step_y(&mut e_row);
             for px in left..=right {
-                let (alpha, beta, gamma) = t.barycentric_coordinates(px as f32, py as f32);
-                if !Triangle::inside_triangle(alpha, beta, gamma) {
-                    continue;
+                let center = e;
+                edges.step_x(&mut e);
+                // 先对每个采样点做覆盖和深度测试，记录通过的采样点
+                let index = self.get_index(px, py) * samples;
+                let mut mask = 0u32;
+                let mut depths = [0.; 8];
+                let mut shading_point = None;
+                for (s, &(ox, oy)) in self.pattern.iter().enumerate() {
+                    let es = edges.offset(&center, ox, oy);
+                    if !edges.covers(&es) {
+                        continue;
+                    }
+                    // 像素中心不在三角形内时，改在第一个被覆盖的采样点处着色
+                    shading_point.get_or_insert(es);
+                    // NDC 下的 z 在屏幕空间中是线性的，直接用重心坐标插值
+                    let (alpha, beta, gamma) = edges.barycentric(&es);
+                    let z = alpha * t.v[0].z + beta * t.v[1].z + gamma * t.v[2].z;
+                    if self.depth_buf[index + s] < z {
+                        // 半透明物体不遮挡之后绘制的物体，不写入深度
+                        if self.blend.is_none() {
+                            self.depth_buf[index + s] = z;
+                        }
+                        depths[s] = z;
+                        mask |= 1 << s;
+                    }
                 }
-                // 透视校正插值，在相机坐标系下与视平面的距离
-                let z = 1.0 / (alpha / t.v[0].w + beta / t.v[1].w + gamma / t.v[2].w);
-                macro_rules! interp {
-                    ($p0:expr, $p1:expr, $p2:expr) => {
-                        z * (alpha * $p0 / t.v[0].w
-                            + beta * $p1 / t.v[1].w
-                            + gamma * $p2 / t.v[2].w)
-                    };
+                if mask == 0 {
+                    continue;
                 }
-                let index = self.get_index(px, py);
-                if self.depth_buf[index] < z {
-                    let interp_color = interp!(t.color[0], t.color[1], t.color[2]);
-                    let interp_normal = interp!(t.normal[0], t.normal[1], t.normal[2]).normalize();
-                    let interp_tex_coords = interp!(t.texture[0], t.texture[1], t.texture[2]);
-                    let interp_model_pos = interp!(model_pos[0], model_pos[1], model_pos[2]);
-                    let payload = Payload {
-                        color: interp_color,
-                        normal: interp_normal,
-                        point: interp_model_pos.truncate(),
-                        tex_coords: interp_tex_coords,
-                        texture,
+                // 每个像素只着色一次
+                let shading_point = if edges.covers(&center) {
+                    center
+                } else {
+                    shading_point.unwrap()
+                };
+                // 透视校正插值，先按 1/w 加权，再除以插值得到的 1/w
+                let interp = |e: [i64; 3]| {
+                    let (alpha, beta, gamma) = edges.barycentric(&e);
+                    let (alpha, beta, gamma) =
+                        (alpha / t.v[0].w, beta / t.v[1].w, gamma / t.v[2].w);
+                    let z = 1.0 / (alpha + beta + gamma);
+                    t.varyings[0] * (alpha * z)
+                        + t.varyings[1] * (beta * z)
+                        + t.varyings[2] * (gamma * z)
+                };
+                let varyings = interp(shading_point);
+                // 在右侧和上方相邻像素处插值，差分得到屏幕空间导数
+                let ddx = interp(edges.offset(&shading_point, 16, 0)) + varyings * -1.;
+                let ddy = interp(edges.offset(&shading_point, 0, 16)) + varyings * -1.;
+                let payload = Payload {
+                    varyings,
+                    ddx,
+                    ddy,
+                    texture,
+                    material: object.material.as_deref(),
+                    has_texcoords,
+                };
+                let color = shader.shading(payload);
+                for (s, &depth) in depths[..samples].iter().enumerate() {
+                    if mask & (1 << s) == 0 {
+                        continue;
+                    }
+                    let Some(blend) = self.blend else {
+                        self.color_buf[index + s] = color.truncate().extend(1.);
+                        continue;
                     };
-                    let color = self.shader.shading(payload);
-
-                    self.depth_buf[index] = z;
-                    self.frame_buf[index] = color;
+                    if let Some(fragments) = &mut self.fragments {
+                        // tile 的左下角总是 TILE_SIZE 的整数倍
+                        let tiles_x = self.width.div_ceil(TILE_SIZE);
+                        let tile_id =
+                            (py - self.bottom) / TILE_SIZE * tiles_x + (px - self.left) / TILE_SIZE;
+                        let (x, y) = (px % TILE_SIZE, py % TILE_SIZE);
+                        if fragments[tile_id].push(x, y, s, depth, color) {
+                            continue;
+                        }
+                    }
+                    let dst = &mut self.color_buf[index + s];
+                    *dst = blend.blend(color, *dst);
                 }
             }
         }
     }
 }
 
+// 输出图像
+impl<S: Shader> Rasterizer<S> {
+    /// 将当前帧转换为 RGB 图像，第一行是屏幕的最上方
+    pub fn to_image(&self) -> RgbImage {
+        let mut img = RgbImage::new(self.width as u32, self.height as u32);
+        for (p, c) in img.pixels_mut().zip(&self.frame_buf) {
+            *p = Rgb([c.r, c.g, c.b]);
+        }
+        img
+    }
+
+    /// 将当前帧保存为 PNG 图像
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.to_image()
+            .save_with_format(path, ImageFormat::Png)
+            .map_err(Into::into)
+    }
+
+    /// 将当前帧保存为二进制的 PPM (P6) 图像
+    pub fn save_ppm<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "P6\n{} {}\n255\n", self.width, self.height)?;
+        file.write_all(self.to_image().as_raw())?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
 // builder 相关
 impl<S: Shader> Rasterizer<S> {
     pub fn view(&mut self, view: Mat4) -> &mut Self {
@@ -169,6 +859,39 @@ impl<S: Shader> Rasterizer<S> {
         self.projection = projection;
         self
     }
+    pub fn cull_mode(&mut self, cull_mode: CullMode) -> &mut Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+    pub fn front_face(&mut self, front_face: FrontFace) -> &mut Self {
+        self.front_face = front_face;
+        self
+    }
+    pub fn render_mode(&mut self, render_mode: RenderMode) -> &mut Self {
+        self.render_mode = render_mode;
+        self
+    }
+    pub fn blend_mode(&mut self, blend_mode: BlendMode) -> &mut Self {
+        self.blend_mode = blend_mode;
+        self
+    }
+    pub fn transparency(&mut self, transparency: Transparency) -> &mut Self {
+        self.transparency = transparency;
+        self.fragment_tiles.clear();
+        self
+    }
+    pub fn tone_mapping(&mut self, tone_mapping: ToneMapping) -> &mut Self {
+        self.tone_mapping = tone_mapping;
+        self
+    }
+    pub fn exposure(&mut self, exposure: f32) -> &mut Self {
+        self.exposure = exposure;
+        self
+    }
+    pub fn output_color_space(&mut self, output_color_space: ColorSpace) -> &mut Self {
+        self.output_color_space = output_color_space;
+        self
+    }
 }
 
 // 基本原语，包括像素、直线
@@ -202,6 +925,8 @@ impl<S: Shader> Rasterizer<S> {
             }
         }
         /// 中点 Bresenham 算法
+        // x0、y0 的部分更新没有被读取，保留是为了与算法的描述一一对应
+        #[allow(unused_assignments)]
         fn bresenham_center<S: Shader>(
             rst: &mut Rasterizer<S>,
             from: Vec2,
@@ -274,6 +999,7 @@ impl<S: Shader> Rasterizer<S> {
             }
         }
         /// 改进 Bresenham 算法。当前的实现慢于上面的中点 Bresenham 算法
+        #[allow(unused_assignments)]
         fn _bresenham<S: Shader>(rst: &mut Rasterizer<S>, from: Vec2, to: Vec2, color: BGRA8) {
             let (x0, y0) = (from.x as i32, from.y as i32);
             let (x1, y1) = (to.x as i32, to.y as i32);
@@ -389,10 +1115,12 @@ impl<S: Shader> Rasterizer<S> {
             self.frame_buf[index4] = color;
             if d > 0 {
                 d += 4 * a * a * (2 * (y0 - y) + 3) - 8 * b * b * (x0 - x + 1);
-                if index2 % self.width == 0 || index3 % self.width == 0 {
+                if index2.is_multiple_of(self.width) || index3.is_multiple_of(self.width) {
                     break;
                 }
-                if (index1 + 1) % self.width == 0 || (index4 + 1) % self.width == 0 {
+                if (index1 + 1).is_multiple_of(self.width)
+                    || (index4 + 1).is_multiple_of(self.width)
+                {
                     break;
                 }
                 x0 += 1;
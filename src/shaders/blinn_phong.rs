@@ -1,9 +1,11 @@
-use glam::{vec3, Vec3};
-use rgb::alt::BGRA8;
+use glam::{vec3, Vec3, Vec4};
 
-use crate::color;
+use crate::{
+    object::Object,
+    shadow::{self, ShadowMap, ShadowSettings},
+};
 
-use super::{light, Light, Payload, Shader};
+use super::{illuminate, light, Attributes, Light, Payload, StandardShader};
 
 pub struct BlinnPhongShader {
     eye_pos: Vec3,
@@ -16,6 +18,10 @@ pub struct BlinnPhongShader {
     spec_coeff: Vec3,
     /// 高光指数
     spec_exp: i32,
+    /// 不透明度，作为着色结果的 alpha
+    opacity: f32,
+    /// 与 `lights` 一一对应的阴影贴图，为 `None` 的光源不产生阴影
+    shadow_maps: Vec<Option<ShadowMap>>,
 }
 
 impl BlinnPhongShader {
@@ -34,6 +40,8 @@ impl BlinnPhongShader {
             amb_intensity,
             spec_coeff,
             spec_exp,
+            opacity: 1.,
+            shadow_maps: Vec::new(),
         }
     }
     pub fn example(eye_pos: Vec3) -> Self {
@@ -53,30 +61,62 @@ impl BlinnPhongShader {
             amb_intensity,
             spec_coeff,
             spec_exp,
+            opacity: 1.,
+            shadow_maps: Vec::new(),
         }
     }
     pub fn eye_pos(&mut self, eye_pos: Vec3) -> &mut Self {
         self.eye_pos = eye_pos;
         self
     }
+    pub fn opacity(&mut self, opacity: f32) -> &mut Self {
+        self.opacity = opacity;
+        self
+    }
+    pub fn shadow_maps(&mut self, shadow_maps: Vec<Option<ShadowMap>>) -> &mut Self {
+        self.shadow_maps = shadow_maps;
+        self
+    }
+    /// 为每个光源渲染 `objects` 的阴影贴图
+    pub fn cast_shadows(&mut self, objects: &[&Object], settings: ShadowSettings) -> &mut Self {
+        self.shadow_maps = shadow::shadow_maps(&self.lights, settings, objects);
+        self
+    }
 }
 
-impl Shader for BlinnPhongShader {
-    fn shading(&self, payload: Payload) -> BGRA8 {
-        let mut result_color = Vec3::new(0., 0., 0.);
-        for &Light { source, intensity } in &self.lights {
-            let r = source - payload.point;
-            let l = r.normalize();
-            let r2w = 1. / r.length_squared();
-            let l_diffuse = payload.color * intensity * r2w * payload.normal.dot(l).max(0.);
-            let v = (self.eye_pos - payload.point).normalize();
+impl StandardShader for BlinnPhongShader {
+    fn shading(&self, payload: Payload<Attributes>) -> Vec4 {
+        let Attributes {
+            color,
+            normal,
+            point,
+            ..
+        } = payload.varyings;
+        let normal = normal.normalize();
+        // 有材质时以材质的参数为准，漫反射系数不再取顶点颜色
+        let (diffuse, spec_coeff, spec_exp, amb_coeff, opacity) = match payload.material {
+            Some(m) => (
+                m.diffuse,
+                m.specular,
+                m.shininess,
+                m.ambient,
+                m.dissolve * self.opacity,
+            ),
+            None => (
+                color,
+                self.spec_coeff,
+                self.spec_exp as f32,
+                self.amb_coeff,
+                self.opacity,
+            ),
+        };
+        let v = (self.eye_pos - point).normalize();
+        let mut result_color = illuminate(&self.lights, &self.shadow_maps, point, normal, |l| {
             let h = (l + v).normalize();
-            let l_spec = self.spec_coeff
-                * intensity
-                * r2w
-                * payload.normal.dot(h).max(0.).powi(self.spec_exp);
-            result_color += l_diffuse + l_spec + self.amb_coeff * self.amb_intensity;
-        }
-        color::to_bgra(result_color)
+            diffuse * normal.dot(l).max(0.) + spec_coeff * normal.dot(h).max(0.).powf(spec_exp)
+        });
+        // 环境光按光源的个数累加
+        result_color += amb_coeff * self.amb_intensity * self.lights.len() as f32;
+        result_color.extend(opacity)
     }
 }
@@ -1,9 +1,6 @@
-use glam::{vec3, Mat3, Vec2, Vec3};
-use rgb::alt::BGRA8;
+use glam::{vec3, Vec2, Vec3, Vec4};
 
-use crate::color::to_bgra;
-
-use super::{Payload, Shader};
+use super::{tbn, Attributes, Payload, StandardShader};
 
 pub struct BumpShader {
     eye_pos: Vec3,
@@ -19,18 +16,23 @@ impl BumpShader {
     }
 }
 
-impl Shader for BumpShader {
-    fn shading(&self, payload: Payload) -> BGRA8 {
+impl StandardShader for BumpShader {
+    fn shading(&self, payload: Payload<Attributes>) -> Vec4 {
+        let Attributes {
+            normal,
+            tex_coords,
+            tangent,
+            ..
+        } = payload.varyings;
+        let normal = normal.normalize();
+        let Some(texture) = payload.texture else {
+            // 没有高度图时不做扰动
+            return normal.extend(1.);
+        };
         let kh = 0.2;
         let kn = 0.1;
-        let Vec3 { x, y, z } = payload.normal;
-
-        let xz = (x * x + z * z).sqrt();
-        let t = vec3(x * y / xz, xz, z * y / xz);
-        let b = payload.normal.cross(t);
-        let tbn = Mat3::from_cols(t, b, payload.normal);
-        let Vec2 { x: u, y: v } = payload.tex_coords;
-        let texture = payload.texture;
+        let tbn = tbn(normal, tangent);
+        let Vec2 { x: u, y: v } = tex_coords;
         #[rustfmt::skip]
         let d_u = kh * kn * (
             texture.pixel(u + 1. / texture.width(), v).length()
@@ -42,7 +44,6 @@ impl Shader for BumpShader {
             - texture.pixel(u, v).length()
         ) * 255.;
         let ln = vec3(-d_u, -d_v, 1.);
-        let normal = (tbn * ln).normalize();
-        to_bgra(normal)
+        (tbn * ln).normalize().extend(1.)
     }
 }
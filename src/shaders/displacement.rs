@@ -1,9 +1,8 @@
-use glam::{vec3, Mat3, Vec2, Vec3};
-use rgb::alt::BGRA8;
+use glam::{vec3, Mat3, Vec2, Vec3, Vec4};
 
-use crate::color::to_bgra;
+use crate::texture::Texture;
 
-use super::{light, Light, Payload, Shader};
+use super::{illuminate, light, tbn, Attributes, Light, Payload, StandardShader};
 
 pub struct DisplacementShader {
     eye_pos: Vec3,
@@ -61,43 +60,50 @@ impl DisplacementShader {
     }
 }
 
-impl Shader for DisplacementShader {
-    fn shading(&self, payload: Payload) -> BGRA8 {
-        let kh = 0.2;
-        let kn = 0.1;
-        let Vec3 { x, y, z } = payload.normal;
-
-        let xz = (x * x + z * z).sqrt();
-        let t = vec3(x * y / xz, xz, z * y / xz);
-        let b = payload.normal.cross(t);
-        let tbn = Mat3::from_cols(t, b, payload.normal);
-        let Vec2 { x: u, y: v } = payload.tex_coords;
-        let texture = payload.texture;
-        #[rustfmt::skip]
-        let d_u = kh * kn * (
-            texture.pixel(u + 1. / texture.width(), v).length()
-            - texture.pixel(u, v).length()
-        ) * 255.;
-        #[rustfmt::skip]
-        let d_v = kh * kn * (
-            texture.pixel(u, v + 1. / texture.height()).length()
-            - texture.pixel(u, v).length()
-        ) * 255.;
-        let point = payload.point + kn * payload.normal * texture.pixel(u, v).length() * 255.;
-        let ln = vec3(-d_u, -d_v, 1.);
-        let normal = (tbn * ln).normalize();
-        let mut result_color = vec3(0., 0., 0.);
-        for &Light { source, intensity } in &self.lights {
-            let r = source - point;
-            let l = r.normalize();
-            let r2w = 1. / r.length_squared();
-            let l_diffuse = payload.color * intensity * r2w * normal.dot(l).max(0.);
-            let v = (self.eye_pos - point).normalize();
+impl StandardShader for DisplacementShader {
+    fn shading(&self, payload: Payload<Attributes>) -> Vec4 {
+        let Attributes {
+            color,
+            normal,
+            point,
+            tex_coords,
+            tangent,
+        } = payload.varyings;
+        let normal = normal.normalize();
+        // 没有高度图时不做位移和扰动
+        let (point, normal) = match payload.texture {
+            Some(texture) => displace(texture, point, tbn(normal, tangent), tex_coords),
+            None => (point, normal),
+        };
+        let v = (self.eye_pos - point).normalize();
+        let mut result_color = illuminate(&self.lights, &[], point, normal, |l| {
             let h = (l + v).normalize();
-            let l_spec =
-                self.spec_coeff * intensity * r2w * normal.dot(h).max(0.).powi(self.spec_exp);
-            result_color += l_diffuse + l_spec + self.amb_coeff * self.amb_intensity;
-        }
-        to_bgra(result_color)
+            color * normal.dot(l).max(0.)
+                + self.spec_coeff * normal.dot(h).max(0.).powi(self.spec_exp)
+        });
+        // 环境光按光源的个数累加
+        result_color += self.amb_coeff * self.amb_intensity * self.lights.len() as f32;
+        result_color.extend(1.)
     }
 }
+
+/// 按高度图沿法线移动着色点，并在切线空间 `tbn` 中扰动法线
+fn displace(texture: &Texture, point: Vec3, tbn: Mat3, tex_coords: Vec2) -> (Vec3, Vec3) {
+    let kh = 0.2;
+    let kn = 0.1;
+    let normal = tbn.z_axis;
+    let Vec2 { x: u, y: v } = tex_coords;
+    #[rustfmt::skip]
+    let d_u = kh * kn * (
+        texture.pixel(u + 1. / texture.width(), v).length()
+        - texture.pixel(u, v).length()
+    ) * 255.;
+    #[rustfmt::skip]
+    let d_v = kh * kn * (
+        texture.pixel(u, v + 1. / texture.height()).length()
+        - texture.pixel(u, v).length()
+    ) * 255.;
+    let point = point + kn * normal * texture.pixel(u, v).length() * 255.;
+    let ln = vec3(-d_u, -d_v, 1.);
+    (point, (tbn * ln).normalize())
+}
@@ -1,16 +1,11 @@
-use rgb::alt::BGRA8;
+use glam::Vec4;
 
-use super::{Payload, Shader};
+use super::{Attributes, Payload, StandardShader};
 
 pub struct EmptyShader;
 
-impl Shader for EmptyShader {
-    fn shading(&self, payload: Payload) -> BGRA8 {
-        BGRA8 {
-            b: payload.color.x as u8,
-            g: payload.color.y as u8,
-            r: payload.color.z as u8,
-            a: 0,
-        }
+impl StandardShader for EmptyShader {
+    fn shading(&self, payload: Payload<Attributes>) -> Vec4 {
+        payload.varyings.color.extend(1.)
     }
 }
@@ -0,0 +1,161 @@
+use glam::Vec3;
+
+use crate::shadow::{self, ShadowMap};
+
+/// 光源
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Light {
+    /// 点光源，光强随距离的平方衰减
+    Point { source: Vec3, intensity: Vec3 },
+    /// 平行光，`direction` 为光线前进的方向。`intensity` 为垂直照射时的辐照度，不随距离衰减
+    Directional { direction: Vec3, intensity: Vec3 },
+    /// 聚光灯，`inner`、`outer` 为内外锥的半角，以角度表示
+    ///
+    /// 内锥以内与点光源相同，内外锥之间平滑减弱到 0，外锥以外没有光照
+    Spot {
+        source: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        inner: f32,
+        outer: f32,
+    },
+    /// 矩形面光源，`half_u`、`half_v` 为中心到两条边的向量，只朝 `half_u × half_v` 的方向发光
+    ///
+    /// 用 `samples x samples` 个均匀分布的点光源近似，`intensity` 为整个光源在法线方向上的光强
+    Area {
+        center: Vec3,
+        half_u: Vec3,
+        half_v: Vec3,
+        intensity: Vec3,
+        samples: u32,
+    },
+}
+
+impl Light {
+    pub const fn point(source: Vec3, intensity: Vec3) -> Self {
+        Light::Point { source, intensity }
+    }
+    pub fn directional(direction: Vec3, intensity: Vec3) -> Self {
+        Light::Directional {
+            direction: direction.normalize(),
+            intensity,
+        }
+    }
+    pub fn spot(source: Vec3, direction: Vec3, intensity: Vec3, inner: f32, outer: f32) -> Self {
+        Light::Spot {
+            source,
+            direction: direction.normalize(),
+            intensity,
+            inner,
+            outer,
+        }
+    }
+    pub const fn area(
+        center: Vec3,
+        half_u: Vec3,
+        half_v: Vec3,
+        intensity: Vec3,
+        samples: u32,
+    ) -> Self {
+        Light::Area {
+            center,
+            half_u,
+            half_v,
+            intensity,
+            samples,
+        }
+    }
+
+    /// 光源的位置，平行光没有位置
+    pub fn position(&self) -> Option<Vec3> {
+        match *self {
+            Light::Point { source, .. } | Light::Spot { source, .. } => Some(source),
+            Light::Area { center, .. } => Some(center),
+            Light::Directional { .. } => None,
+        }
+    }
+
+    /// 对照射到 `point` 的每一束光调用 `f(l, radiance)`
+    ///
+    /// `l` 为指向光源的单位向量，`radiance` 为已经计入距离衰减的入射光强。面光源会调用多次
+    pub fn illuminate(&self, point: Vec3, mut f: impl FnMut(Vec3, Vec3)) {
+        match *self {
+            Light::Point { source, intensity } => {
+                let r = source - point;
+                f(r.normalize(), intensity / r.length_squared());
+            }
+            Light::Directional {
+                direction,
+                intensity,
+            } => f(-direction, intensity),
+            Light::Spot {
+                source,
+                direction,
+                intensity,
+                inner,
+                outer,
+            } => {
+                let r = source - point;
+                let l = r.normalize();
+                let (cos_inner, cos_outer) = (inner.to_radians().cos(), outer.to_radians().cos());
+                let t = ((-l).dot(direction) - cos_outer) / (cos_inner - cos_outer).max(1e-6);
+                let t = t.clamp(0., 1.);
+                let falloff = t * t * (3. - 2. * t);
+                if falloff > 0. {
+                    f(l, intensity * falloff / r.length_squared());
+                }
+            }
+            Light::Area {
+                center,
+                half_u,
+                half_v,
+                intensity,
+                samples,
+            } => {
+                let n = half_u.cross(half_v).normalize();
+                let samples = samples.max(1);
+                let intensity = intensity / (samples * samples) as f32;
+                for i in 0..samples {
+                    for j in 0..samples {
+                        // 取每个小格的中心
+                        let s = (2 * i + 1) as f32 / samples as f32 - 1.;
+                        let t = (2 * j + 1) as f32 / samples as f32 - 1.;
+                        let r = center + half_u * s + half_v * t - point;
+                        let l = r.normalize();
+                        let cos = (-l).dot(n);
+                        if cos > 0. {
+                            f(l, intensity * cos / r.length_squared());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 点光源
+#[inline]
+pub const fn light(source: Vec3, intensity: Vec3) -> Light {
+    Light::point(source, intensity)
+}
+
+/// 对所有光源的每一束入射光求 `radiance * brdf(l)` 之和，各光源的贡献乘以其阴影贴图给出的可见比例
+///
+/// `shadow_maps` 与 `lights` 一一对应，可以为空；`brdf` 的参数为指向光源的单位向量，应当已经计入余弦项
+pub fn illuminate(
+    lights: &[Light],
+    shadow_maps: &[Option<ShadowMap>],
+    point: Vec3,
+    normal: Vec3,
+    mut brdf: impl FnMut(Vec3) -> Vec3,
+) -> Vec3 {
+    let mut result = Vec3::ZERO;
+    for (i, light) in lights.iter().enumerate() {
+        let mut sum = Vec3::ZERO;
+        light.illuminate(point, |l, radiance| sum += radiance * brdf(l));
+        if sum != Vec3::ZERO {
+            result += sum * shadow::visibility(shadow_maps, i, point, normal);
+        }
+    }
+    result
+}
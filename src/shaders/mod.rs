@@ -2,40 +2,179 @@ mod blinn_phong;
 mod bump;
 mod displacement;
 mod empty;
+mod light;
 mod normal;
+mod normal_map;
+mod pbr;
 mod texture;
 
-use glam::{Vec2, Vec3};
-use rgb::alt::BGRA8;
+use glam::{Mat3, Mat4, Vec2, Vec3, Vec4};
+use std::ops::{Add, Mul};
 
-use crate::texture::Texture;
+use crate::{object::Material, texture::Texture};
 
 pub use blinn_phong::BlinnPhongShader;
 pub use bump::BumpShader;
 pub use displacement::DisplacementShader;
 pub use empty::EmptyShader;
+pub use light::{illuminate, light, Light};
 pub use normal::NormalShader;
+pub use normal_map::{NormalMapFormat, NormalMapShader};
+pub use pbr::PbrShader;
 pub use texture::TextureShader;
 
-pub struct Payload<'a> {
+pub(crate) use pbr::distribution_ggx;
+
+/// 可以在三角形内插值的顶点属性
+///
+/// 光栅化时按 `v0 * a + v1 * b + v2 * c` 的形式做透视校正插值，所以只需要支持加法和数乘。
+/// 任何满足这些约束的类型（包括 `f32`、`Vec3` 等）都自动实现了该 trait
+pub trait Varyings: Copy + Send + Sync + Add<Output = Self> + Mul<f32, Output = Self> {}
+
+impl<T> Varyings for T where T: Copy + Send + Sync + Add<Output = Self> + Mul<f32, Output = Self> {}
+
+/// 内置着色器使用的插值属性
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Attributes {
     pub color: Vec3,
+    /// 插值后的法线不再是单位向量，使用前需要归一化
     pub normal: Vec3,
+    /// 世界坐标
     pub point: Vec3,
     pub tex_coords: Vec2,
-    pub texture: &'a Texture,
+    /// 世界坐标下的切线，w 为副切线的方向，见 [`tbn`]
+    pub tangent: Vec4,
+}
+
+impl Add for Attributes {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            color: self.color + rhs.color,
+            normal: self.normal + rhs.normal,
+            point: self.point + rhs.point,
+            tex_coords: self.tex_coords + rhs.tex_coords,
+            tangent: self.tangent + rhs.tangent,
+        }
+    }
+}
+
+impl Mul<f32> for Attributes {
+    type Output = Self;
+    fn mul(self, rhs: f32) -> Self {
+        Self {
+            color: self.color * rhs,
+            normal: self.normal * rhs,
+            point: self.point * rhs,
+            tex_coords: self.tex_coords * rhs,
+            tangent: self.tangent * rhs,
+        }
+    }
+}
+
+pub struct Payload<'a, V> {
+    /// 在当前像素处插值得到的属性
+    pub varyings: V,
+    /// 属性沿屏幕 x、y 方向移动一个像素时的变化量，可用于 mipmap 采样
+    pub ddx: V,
+    pub ddy: V,
+    /// 物体的纹理，物体没有纹理或纹理坐标时为 `None`
+    pub texture: Option<&'a Texture>,
+    /// 物体的材质，着色器可以用其中的参数代替自身的默认值
+    pub material: Option<&'a Material>,
+    /// 物体是否有纹理坐标，没有时 `tex_coords` 恒为零，不应采样材质中的贴图
+    pub has_texcoords: bool,
 }
 
-pub trait Shader {
-    fn shading(&self, payload: Payload) -> BGRA8;
+/// 顶点着色器的输入，即模型中一个顶点的属性
+#[derive(Debug, Clone, Copy)]
+pub struct Vertex {
+    /// 模型坐标
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub tex_coords: Vec2,
+    pub color: Vec3,
+    /// 切线，w 为 ±1，物体没有切线时为零向量
+    pub tangent: Vec4,
 }
 
-#[derive(Clone, Copy)]
-pub struct Light {
-    pub source: Vec3,
-    pub intensity: Vec3,
+/// 一次绘制中所有顶点共享的数据
+#[derive(Debug, Clone, Copy)]
+pub struct Uniforms {
+    pub model: Mat4,
+    pub view: Mat4,
+    pub projection: Mat4,
+    /// 模型矩阵的逆转置，用于变换法线
+    pub normal_matrix: Mat4,
 }
 
-#[inline]
-pub const fn light(source: Vec3, intensity: Vec3) -> Light {
-    Light { source, intensity }
+/// 顶点着色器的输出
+#[derive(Debug, Clone, Copy)]
+pub struct VertexOutput<V> {
+    /// 齐次裁剪空间坐标。与 `transform::perspective` 一致，可见区域满足 `w < 0`
+    pub position: Vec4,
+    /// 会在三角形内透视校正插值后交给片元着色器
+    pub varyings: V,
+}
+
+/// 内置着色器共用的顶点着色器，依次做模型、视图、投影变换，并把法线变换到世界坐标系
+pub fn standard_vertex(vertex: &Vertex, uniforms: &Uniforms) -> VertexOutput<Attributes> {
+    let point = uniforms.model * vertex.position.extend(1.);
+    VertexOutput {
+        position: uniforms.projection * uniforms.view * point,
+        varyings: Attributes {
+            color: vertex.color,
+            normal: (uniforms.normal_matrix * vertex.normal.extend(0.)).truncate(),
+            point: point.truncate(),
+            tex_coords: vertex.tex_coords,
+            // 切线位于表面内，与位置一样用模型矩阵变换
+            tangent: (uniforms.model * vertex.tangent.truncate().extend(0.))
+                .truncate()
+                .extend(vertex.tangent.w),
+        },
+    }
+}
+
+/// 由单位法线和插值后的切线构造切线空间到世界坐标的变换，三列依次为切线、副切线、法线
+///
+/// 与 MikkTSpace 的约定相同，切线先对法线正交化，副切线为 `sign(w) * normal.cross(tangent)`。
+/// 没有切线时任取一组与法线正交的基
+pub fn tbn(normal: Vec3, tangent: Vec4) -> Mat3 {
+    let t = tangent.truncate();
+    let t = (t - normal * normal.dot(t))
+        .try_normalize()
+        .unwrap_or_else(|| normal.any_orthonormal_vector());
+    let sign = if tangent.w < 0. { -1. } else { 1. };
+    Mat3::from_cols(t, sign * normal.cross(t), normal)
+}
+
+/// 着色器会在多个线程中同时被调用，因此要求 `Sync`
+pub trait Shader: Sync {
+    /// 顶点着色器输出、片元着色器输入的插值属性
+    type Varyings: Varyings;
+    /// 顶点着色器
+    fn vertex(&self, vertex: &Vertex, uniforms: &Uniforms) -> VertexOutput<Self::Varyings>;
+    /// 片元着色器，返回线性空间的颜色（bgr 顺序）和 alpha
+    ///
+    /// 颜色可以超出 `[0, 1]`，由光栅化器做色调映射；alpha 只在半透明绘制时参与混合
+    fn shading(&self, payload: Payload<Self::Varyings>) -> Vec4;
+}
+
+/// 以 [`standard_vertex`] 为顶点着色器、[`Attributes`] 为插值属性的着色器，只需要实现片元着色器
+///
+/// 实现了该 trait 的类型自动实现 [`Shader`]。需要自定义顶点着色器时应直接实现 [`Shader`]
+pub trait StandardShader: Sync {
+    /// 片元着色器，含义同 [`Shader::shading`]
+    fn shading(&self, payload: Payload<Attributes>) -> Vec4;
+}
+
+impl<T: StandardShader> Shader for T {
+    type Varyings = Attributes;
+
+    fn vertex(&self, vertex: &Vertex, uniforms: &Uniforms) -> VertexOutput<Attributes> {
+        standard_vertex(vertex, uniforms)
+    }
+    fn shading(&self, payload: Payload<Attributes>) -> Vec4 {
+        StandardShader::shading(self, payload)
+    }
 }
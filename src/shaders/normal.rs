@@ -1,16 +1,11 @@
-use rgb::alt::BGRA8;
+use glam::Vec4;
 
-use super::{Payload, Shader};
+use super::{Attributes, Payload, StandardShader};
 
 pub struct NormalShader;
 
-impl Shader for NormalShader {
-    fn shading(&self, payload: Payload) -> BGRA8 {
-        BGRA8 {
-            b: ((payload.normal.x + 1.) * 0.5 * 255.) as u8,
-            g: ((payload.normal.y + 1.) * 0.5 * 255.) as u8,
-            r: ((payload.normal.z + 1.) * 0.5 * 255.) as u8,
-            a: 0,
-        }
+impl StandardShader for NormalShader {
+    fn shading(&self, payload: Payload<Attributes>) -> Vec4 {
+        ((payload.varyings.normal.normalize() + 1.) * 0.5).extend(1.)
     }
 }
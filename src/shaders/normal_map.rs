@@ -0,0 +1,87 @@
+use glam::{vec3, Vec3, Vec4};
+
+use crate::texture::{Filter, Sampler, Texture};
+
+use super::{tbn, Attributes, Payload, StandardShader, TextureShader};
+
+/// 法线贴图 g 通道的朝向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NormalMapFormat {
+    /// g 通道指向纹理坐标 v 增大的方向，Blender、Maya 等导出的格式
+    #[default]
+    OpenGl,
+    /// g 通道指向 v 减小的方向，Unreal、3ds Max 等导出的格式
+    DirectX,
+}
+
+/// 切线空间法线贴图着色器
+///
+/// 用法线贴图扰动法线后，按 [`TextureShader`] 的 Blinn-Phong 模型着色
+pub struct NormalMapShader {
+    base: TextureShader,
+    /// 未设置时使用物体材质中的法线贴图
+    normal_map: Option<Texture>,
+    format: NormalMapFormat,
+    sampler: Sampler,
+}
+
+impl NormalMapShader {
+    pub fn new(base: TextureShader) -> Self {
+        Self {
+            base,
+            normal_map: None,
+            format: NormalMapFormat::default(),
+            sampler: Sampler::new(Filter::Trilinear),
+        }
+    }
+    pub fn example(eye_pos: Vec3) -> Self {
+        Self::new(TextureShader::example(eye_pos))
+    }
+    /// 负责光照的着色器，可以用来设置视点、阴影等
+    pub fn base(&mut self) -> &mut TextureShader {
+        &mut self.base
+    }
+    /// 法线贴图应以 `Texture::linear` 读取
+    pub fn normal_map(&mut self, map: Texture) -> &mut Self {
+        self.normal_map = Some(map);
+        self
+    }
+    pub fn format(&mut self, format: NormalMapFormat) -> &mut Self {
+        self.format = format;
+        self
+    }
+    pub fn sampler(&mut self, sampler: Sampler) -> &mut Self {
+        self.sampler = sampler;
+        self
+    }
+}
+
+impl StandardShader for NormalMapShader {
+    fn shading(&self, payload: Payload<Attributes>) -> Vec4 {
+        let Attributes {
+            normal,
+            tex_coords,
+            tangent,
+            ..
+        } = payload.varyings;
+        let mut normal = normal.normalize();
+        let map = self
+            .normal_map
+            .as_ref()
+            .or(payload.material.and_then(|m| m.normal_map.as_ref()));
+        // 没有切线说明模型没有纹理坐标，无法采样法线贴图
+        if let Some(map) = map.filter(|_| tangent != Vec4::ZERO) {
+            let (ddx, ddy) = (payload.ddx.tex_coords, payload.ddy.tex_coords);
+            // 纹素按 bgr 排列，从 [0, 1] 映射回 [-1, 1]
+            let texel = map.sample(&self.sampler, tex_coords, ddx, ddy);
+            let mut n = vec3(texel.z, texel.y, texel.x) * 2. - 1.;
+            if self.format == NormalMapFormat::DirectX {
+                n.y = -n.y;
+            }
+            if let Some(n) = (tbn(normal, tangent) * n).try_normalize() {
+                normal = n;
+            }
+        }
+        self.base.shade(&payload, normal)
+    }
+}
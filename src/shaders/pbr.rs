@@ -0,0 +1,229 @@
+use std::{f32::consts::PI, sync::Arc};
+
+use glam::{vec3, Vec3, Vec4};
+
+use crate::{
+    environment::{self, Environment},
+    object::Object,
+    shadow::{self, ShadowMap, ShadowSettings},
+    texture::{Filter, Sampler, Texture},
+};
+
+use super::{illuminate, light, Attributes, Light, Payload, StandardShader};
+
+/// 基于物理的金属度-粗糙度着色器
+///
+/// 镜面反射为 Cook-Torrance 模型（GGX 法线分布、Smith 几何遮蔽、Schlick 菲涅尔），
+/// 漫反射为 Lambert 模型，只有未被镜面反射的能量才会进入漫反射
+pub struct PbrShader {
+    eye_pos: Vec3,
+    pub lights: Vec<Light>,
+    /// 与 `lights` 一一对应的阴影贴图，为 `None` 的光源不产生阴影
+    shadow_maps: Vec<Option<ShadowMap>>,
+    /// 基础色（线性），开启 `use_texture` 时与物体的纹理（没有纹理时为顶点颜色）相乘
+    base_color: Vec3,
+    use_texture: bool,
+    metallic: f32,
+    roughness: f32,
+    /// 环境光遮蔽，只作用于环境光
+    ao: f32,
+    /// 环境光的辐照度，设置了 `environment` 时不再使用
+    ambient: Vec3,
+    /// 基于图像的环境光照，同时提供漫反射和镜面反射
+    environment: Option<Arc<Environment>>,
+    /// 金属度-粗糙度贴图，与 glTF 相同，g 通道为粗糙度、b 通道为金属度，与 `metallic`、`roughness` 相乘
+    metallic_roughness_map: Option<Texture>,
+    /// 环境光遮蔽贴图，取 r 通道，与 `ao` 相乘
+    ao_map: Option<Texture>,
+    sampler: Sampler,
+    /// 不透明度，作为着色结果的 alpha
+    opacity: f32,
+}
+
+impl PbrShader {
+    pub fn new(eye_pos: Vec3, lights: Vec<Light>, metallic: f32, roughness: f32) -> Self {
+        Self {
+            eye_pos,
+            lights,
+            shadow_maps: Vec::new(),
+            base_color: Vec3::ONE,
+            use_texture: true,
+            metallic,
+            roughness,
+            ao: 1.,
+            ambient: vec3(0.03, 0.03, 0.03),
+            environment: None,
+            metallic_roughness_map: None,
+            ao_map: None,
+            sampler: Sampler::new(Filter::Trilinear),
+            opacity: 1.,
+        }
+    }
+    pub fn example(eye_pos: Vec3) -> Self {
+        // 光强约为 Blinn-Phong 示例的 π 倍，因为 Lambert 漫反射要除以 π
+        let lights = vec![
+            light(vec3(20., 20., 20.), vec3(1500., 1500., 1500.)),
+            light(vec3(-20., 20., 0.), vec3(1500., 1500., 1500.)),
+        ];
+        Self::new(eye_pos, lights, 0., 0.5)
+    }
+    pub fn eye_pos(&mut self, eye_pos: Vec3) -> &mut Self {
+        self.eye_pos = eye_pos;
+        self
+    }
+    pub fn base_color(&mut self, base_color: Vec3) -> &mut Self {
+        self.base_color = base_color;
+        self
+    }
+    /// 是否用物体的纹理作为基础色贴图
+    pub fn use_texture(&mut self, use_texture: bool) -> &mut Self {
+        self.use_texture = use_texture;
+        self
+    }
+    pub fn metallic(&mut self, metallic: f32) -> &mut Self {
+        self.metallic = metallic;
+        self
+    }
+    pub fn roughness(&mut self, roughness: f32) -> &mut Self {
+        self.roughness = roughness;
+        self
+    }
+    pub fn ao(&mut self, ao: f32) -> &mut Self {
+        self.ao = ao;
+        self
+    }
+    pub fn ambient(&mut self, ambient: Vec3) -> &mut Self {
+        self.ambient = ambient;
+        self
+    }
+    /// 用环境贴图代替常量的环境光，可以与天空盒共用同一个 [`Environment`]
+    pub fn environment(&mut self, environment: Arc<Environment>) -> &mut Self {
+        self.environment = Some(environment);
+        self
+    }
+    /// 金属度-粗糙度贴图应以 `Texture::linear` 读取
+    pub fn metallic_roughness_map(&mut self, map: Texture) -> &mut Self {
+        self.metallic_roughness_map = Some(map);
+        self
+    }
+    /// 环境光遮蔽贴图应以 `Texture::linear` 读取
+    pub fn ao_map(&mut self, map: Texture) -> &mut Self {
+        self.ao_map = Some(map);
+        self
+    }
+    pub fn sampler(&mut self, sampler: Sampler) -> &mut Self {
+        self.sampler = sampler;
+        self
+    }
+    pub fn opacity(&mut self, opacity: f32) -> &mut Self {
+        self.opacity = opacity;
+        self
+    }
+    pub fn shadow_maps(&mut self, shadow_maps: Vec<Option<ShadowMap>>) -> &mut Self {
+        self.shadow_maps = shadow_maps;
+        self
+    }
+    /// 为每个光源渲染 `objects` 的阴影贴图
+    pub fn cast_shadows(&mut self, objects: &[&Object], settings: ShadowSettings) -> &mut Self {
+        self.shadow_maps = shadow::shadow_maps(&self.lights, settings, objects);
+        self
+    }
+}
+
+impl StandardShader for PbrShader {
+    fn shading(&self, payload: Payload<Attributes>) -> Vec4 {
+        let Attributes {
+            color,
+            normal,
+            point,
+            tex_coords,
+            ..
+        } = payload.varyings;
+        let normal = normal.normalize();
+        let (ddx, ddy) = (payload.ddx.tex_coords, payload.ddy.tex_coords);
+        let sample = |texture: &Texture| texture.sample(&self.sampler, tex_coords, ddx, ddy);
+
+        let mut base_color = self.base_color;
+        // 材质的 Kd 作为基础色的系数，d 作为不透明度的系数
+        let mut opacity = self.opacity;
+        if let Some(m) = payload.material {
+            base_color *= m.diffuse;
+            opacity *= m.dissolve;
+        }
+        // 没有纹理时，没有材质的物体以顶点颜色作为基础色贴图
+        if self.use_texture {
+            match payload.texture {
+                Some(texture) => base_color *= sample(texture),
+                None if payload.material.is_none() => base_color *= color,
+                None => {}
+            }
+        }
+        let (mut metallic, mut roughness) = (self.metallic, self.roughness);
+        if let Some(map) = &self.metallic_roughness_map {
+            let texel = sample(map);
+            roughness *= texel.y;
+            metallic *= texel.x;
+        }
+        let mut ao = self.ao;
+        if let Some(map) = &self.ao_map {
+            ao *= sample(map).z;
+        }
+        let metallic = metallic.clamp(0., 1.);
+        // 粗糙度过小时高光会退化为无穷小的一点
+        let roughness = roughness.clamp(0.04, 1.);
+
+        let v = (self.eye_pos - point).normalize();
+        let n_dot_v = normal.dot(v).max(1e-4);
+        // 非金属的垂直反射率取 0.04，金属则用基础色
+        let f0 = Vec3::splat(0.04).lerp(base_color, metallic);
+        let diffuse = base_color * (1. - metallic) / PI;
+        let mut result_color = illuminate(&self.lights, &self.shadow_maps, point, normal, |l| {
+            let n_dot_l = normal.dot(l);
+            if n_dot_l <= 0. {
+                return Vec3::ZERO;
+            }
+            let h = (l + v).normalize();
+            let f = fresnel_schlick(h.dot(v).max(0.), f0);
+            let specular = f
+                * distribution_ggx(normal.dot(h).max(0.), roughness)
+                * geometry_smith(n_dot_v, n_dot_l, roughness)
+                / (4. * n_dot_v * n_dot_l);
+            ((Vec3::ONE - f) * diffuse + specular) * n_dot_l
+        });
+        result_color += match &self.environment {
+            Some(env) => {
+                // 分离求和近似，粗糙度越大，掠射角处的菲涅尔越弱
+                let f = f0 + (Vec3::splat(1. - roughness).max(f0) - f0) * (1. - n_dot_v).powi(5);
+                let irradiance = env.irradiance(normal);
+                let r = 2. * n_dot_v * normal - v;
+                let ab = environment::env_brdf(n_dot_v, roughness);
+                let specular = env.specular(r, roughness) * (f0 * ab.x + ab.y);
+                ((Vec3::ONE - f) * diffuse * irradiance + specular) * ao
+            }
+            None => self.ambient * base_color * ao,
+        };
+        result_color.extend(opacity)
+    }
+}
+
+/// GGX（Trowbridge-Reitz）法线分布函数
+#[inline]
+pub(crate) fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a2 = roughness.powi(4);
+    let d = n_dot_h * n_dot_h * (a2 - 1.) + 1.;
+    a2 / (PI * d * d)
+}
+
+/// 与 GGX 配套的 Smith 几何遮蔽函数（Schlick 近似），直接光照时 `k = (r + 1)^2 / 8`
+#[inline]
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1.).powi(2) / 8.;
+    let g1 = |n_dot_x: f32| n_dot_x / (n_dot_x * (1. - k) + k);
+    g1(n_dot_v) * g1(n_dot_l)
+}
+
+/// Schlick 菲涅尔近似
+#[inline]
+fn fresnel_schlick(cos_theta: f32, f0: Vec3) -> Vec3 {
+    f0 + (Vec3::ONE - f0) * (1. - cos_theta).clamp(0., 1.).powi(5)
+}
@@ -1,9 +1,12 @@
-use glam::{vec3, Vec3};
-use rgb::alt::BGRA8;
+use glam::{vec3, Vec3, Vec4};
 
-use crate::color;
+use crate::{
+    object::Object,
+    shadow::{self, ShadowMap, ShadowSettings},
+    texture::{Filter, Sampler, Texture},
+};
 
-use super::{light, Light, Payload, Shader};
+use super::{illuminate, light, Attributes, Light, Payload, StandardShader};
 
 pub struct TextureShader {
     eye_pos: Vec3,
@@ -16,6 +19,11 @@ pub struct TextureShader {
     spec_coeff: Vec3,
     /// 高光指数
     spec_exp: i32,
+    /// 不透明度，作为着色结果的 alpha
+    opacity: f32,
+    /// 与 `lights` 一一对应的阴影贴图，为 `None` 的光源不产生阴影
+    shadow_maps: Vec<Option<ShadowMap>>,
+    sampler: Sampler,
 }
 
 impl TextureShader {
@@ -34,6 +42,9 @@ impl TextureShader {
             amb_intensity,
             spec_coeff,
             spec_exp,
+            opacity: 1.,
+            shadow_maps: Vec::new(),
+            sampler: Sampler::new(Filter::Trilinear),
         }
     }
     pub fn example(eye_pos: Vec3) -> Self {
@@ -53,34 +64,85 @@ impl TextureShader {
             amb_intensity,
             spec_coeff,
             spec_exp,
+            opacity: 1.,
+            shadow_maps: Vec::new(),
+            sampler: Sampler::new(Filter::Trilinear),
         }
     }
     pub fn eye_pos(&mut self, eye_pos: Vec3) -> &mut Self {
         self.eye_pos = eye_pos;
         self
     }
+    pub fn opacity(&mut self, opacity: f32) -> &mut Self {
+        self.opacity = opacity;
+        self
+    }
+    pub fn shadow_maps(&mut self, shadow_maps: Vec<Option<ShadowMap>>) -> &mut Self {
+        self.shadow_maps = shadow_maps;
+        self
+    }
+    /// 为每个光源渲染 `objects` 的阴影贴图
+    pub fn cast_shadows(&mut self, objects: &[&Object], settings: ShadowSettings) -> &mut Self {
+        self.shadow_maps = shadow::shadow_maps(&self.lights, settings, objects);
+        self
+    }
+    pub fn sampler(&mut self, sampler: Sampler) -> &mut Self {
+        self.sampler = sampler;
+        self
+    }
+}
+
+impl StandardShader for TextureShader {
+    fn shading(&self, payload: Payload<Attributes>) -> Vec4 {
+        let normal = payload.varyings.normal.normalize();
+        self.shade(&payload, normal)
+    }
 }
 
-impl Shader for TextureShader {
-    fn shading(&self, payload: Payload) -> BGRA8 {
-        let mut result_color = Vec3::new(0., 0., 0.);
-        let diffuse_coeff = payload
-            .texture
-            .pixel(payload.tex_coords.x, payload.tex_coords.y);
-        for &Light { source, intensity } in &self.lights {
-            let r = source - payload.point;
-            let l = r.normalize();
-            let r2w = 1. / r.length_squared();
-            let l_diffuse = diffuse_coeff * intensity * r2w * payload.normal.dot(l).max(0.);
-            let v = (self.eye_pos - payload.point).normalize();
+impl TextureShader {
+    /// 以给定的单位法线代替插值得到的法线着色，供法线贴图等着色器复用
+    pub(crate) fn shade(&self, payload: &Payload<Attributes>, normal: Vec3) -> Vec4 {
+        let Attributes {
+            color,
+            point,
+            tex_coords,
+            ..
+        } = payload.varyings;
+        let (ddx, ddy) = (payload.ddx.tex_coords, payload.ddy.tex_coords);
+        let sample = |texture: &Texture| texture.sample(&self.sampler, tex_coords, ddx, ddy);
+        // 没有纹理时退回到材质的 Kd 或顶点颜色
+        let texel = payload.texture.map(sample);
+        let mut diffuse_coeff = texel.unwrap_or(color);
+        // 有材质时以材质的参数为准，纹理与 Kd 相乘
+        let (spec_coeff, spec_exp, amb_coeff, opacity) = match payload.material {
+            Some(m) => {
+                diffuse_coeff = texel.unwrap_or(Vec3::ONE) * m.diffuse;
+                // 与纹理一样，没有纹理坐标时不采样高光贴图
+                let spec_coeff = match m.specular_map.as_ref().filter(|_| payload.has_texcoords) {
+                    Some(map) => m.specular * sample(map),
+                    None => m.specular,
+                };
+                (
+                    spec_coeff,
+                    m.shininess,
+                    m.ambient,
+                    m.dissolve * self.opacity,
+                )
+            }
+            None => (
+                self.spec_coeff,
+                self.spec_exp as f32,
+                self.amb_coeff,
+                self.opacity,
+            ),
+        };
+        let v = (self.eye_pos - point).normalize();
+        let mut result_color = illuminate(&self.lights, &self.shadow_maps, point, normal, |l| {
             let h = (l + v).normalize();
-            let l_spec = self.spec_coeff
-                * intensity
-                * r2w
-                * payload.normal.dot(h).max(0.).powi(self.spec_exp);
-            result_color += l_diffuse + l_spec;
-        }
-        result_color += self.amb_coeff * self.amb_intensity;
-        color::to_bgra(result_color)
+            diffuse_coeff * normal.dot(l).max(0.)
+                + spec_coeff * normal.dot(h).max(0.).powf(spec_exp)
+        });
+        result_color += amb_coeff * self.amb_intensity;
+        result_color.extend(opacity)
     }
 }
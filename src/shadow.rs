@@ -0,0 +1,307 @@
+use glam::{Mat4, Vec3, Vec4};
+
+use crate::{
+    object::Object,
+    rasterizer::{CullMode, RenderMode},
+    shaders::{Light, Payload, Shader, Uniforms, Vertex, VertexOutput},
+    transform, Rasterizer,
+};
+
+/// 点光源阴影贴图的近远平面距离
+const POINT_Z_NEAR: f32 = 0.1;
+const POINT_Z_FAR: f32 = 100.;
+
+/// 阴影贴图的分辨率与查询参数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+    /// 阴影贴图的边长，点光源的立方体贴图每个面都是这个大小
+    pub size: usize,
+    /// 深度偏移，以世界坐标为单位。被查询点比贴图中的深度远出该值以上才算被遮挡
+    pub bias: f32,
+    /// 查询前沿法线方向把点推出去的距离，以该处阴影贴图纹素的大小为单位，用于消除掠射角处的自阴影
+    pub normal_offset: f32,
+    /// PCF 的半径，在 `(2r + 1) x (2r + 1)` 个纹素上分别比较深度后取平均，为 0 时不做过滤
+    pub pcf_radius: usize,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            size: 512,
+            bias: 0.02,
+            normal_offset: 1.5,
+            pcf_radius: 1,
+        }
+    }
+}
+
+/// 从光源视角渲染的一张深度图
+#[derive(Debug)]
+struct DepthMap {
+    /// 世界坐标到光源坐标系的变换
+    view: Mat4,
+    projection: Mat4,
+    /// 光源坐标系下最近表面的 z，越大离光源越近，没有物体处为负无穷。以左下为原点逐行存放
+    depth: Vec<f32>,
+}
+
+/// 只用于写入深度的着色器
+struct DepthShader;
+
+impl Shader for DepthShader {
+    type Varyings = f32;
+
+    fn vertex(&self, vertex: &Vertex, uniforms: &Uniforms) -> VertexOutput<f32> {
+        let position =
+            uniforms.projection * uniforms.view * uniforms.model * vertex.position.extend(1.);
+        VertexOutput {
+            position,
+            varyings: 0.,
+        }
+    }
+    fn shading(&self, _payload: Payload<f32>) -> Vec4 {
+        Vec4::ZERO
+    }
+}
+
+impl DepthMap {
+    fn render(view: Mat4, projection: Mat4, size: usize, objects: &[&Object]) -> Self {
+        let mut rst = Rasterizer::new(size, size, DepthShader);
+        // 两面都要写入深度，否则只有背面朝向光源的物体投不出阴影
+        rst.view(view)
+            .projection(projection)
+            .cull_mode(CullMode::None)
+            .render_mode(RenderMode::Tiled);
+        rst.clear();
+        for object in objects {
+            rst.draw(object);
+        }
+        // 深度缓冲中是 NDC 下的 z，逆投影回光源坐标系，这样偏移量可以用世界坐标的单位给出
+        let inverse = projection.inverse();
+        let mut depth = vec![f32::NEG_INFINITY; size * size];
+        for y in 0..size {
+            for x in 0..size {
+                let z = rst.depth()[rst.get_index(x, y)];
+                if z.is_finite() {
+                    let p = inverse * Vec4::new(0., 0., z, 1.);
+                    depth[y * size + x] = p.z / p.w;
+                }
+            }
+        }
+        Self {
+            view,
+            projection,
+            depth,
+        }
+    }
+
+    /// `point` 可见的比例，超出贴图范围的点视为不在阴影中
+    fn visibility(&self, point: Vec3, normal: Vec3, settings: &ShadowSettings) -> f32 {
+        // 一个纹素在该点处对应的世界坐标大小，透视投影时与距离成正比
+        let w = (self.projection * self.view * point.extend(1.)).w;
+        let texel = 2. * (w / self.projection.x_axis.x).abs() / settings.size as f32;
+        let point = point + normal * (settings.normal_offset * texel);
+        let p = self.view * point.extend(1.);
+        let clip = self.projection * p;
+        if clip.w >= 0. {
+            return 1.;
+        }
+        let size = settings.size as i64;
+        let x = (0.5 * settings.size as f32 * (clip.x / clip.w + 1.)).floor() as i64;
+        let y = (0.5 * settings.size as f32 * (clip.y / clip.w + 1.)).floor() as i64;
+        let r = settings.pcf_radius as i64;
+        let mut lit = 0;
+        for ty in y - r..=y + r {
+            for tx in x - r..=x + r {
+                let occluded = (0..size).contains(&tx)
+                    && (0..size).contains(&ty)
+                    && self.depth[(ty * size + tx) as usize] > p.z + settings.bias;
+                lit += !occluded as usize;
+            }
+        }
+        lit as f32 / ((2 * r + 1) * (2 * r + 1)) as f32
+    }
+}
+
+/// 一个光源的阴影贴图
+#[derive(Debug)]
+pub struct ShadowMap {
+    settings: ShadowSettings,
+    kind: ShadowKind,
+}
+
+#[derive(Debug)]
+enum ShadowKind {
+    /// 平行光、聚光灯只需要朝一个方向渲染
+    Single(DepthMap),
+    /// 点光源向六个轴方向各渲染一张，依次为 +x、-x、+y、-y、+z、-z
+    Cube {
+        source: Vec3,
+        faces: Box<[DepthMap; 6]>,
+    },
+}
+
+impl ShadowMap {
+    /// 为位于 `source` 的点光源渲染立方体阴影贴图
+    ///
+    /// `z_near`、`z_far` 为光源渲染深度的近远平面**距离**
+    pub fn point(
+        source: Vec3,
+        z_near: f32,
+        z_far: f32,
+        settings: ShadowSettings,
+        objects: &[&Object],
+    ) -> Self {
+        // 视域略大于 90°，留出 PCF 在面的边缘处需要的纹素
+        let margin = 2. * (settings.pcf_radius + 1) as f32 / settings.size as f32;
+        let fovy = 2. * (1. + margin).atan().to_degrees();
+        let projection = transform::perspective(fovy, 1., z_near, z_far);
+        let faces = [
+            (Vec3::X, Vec3::NEG_Y),
+            (Vec3::NEG_X, Vec3::NEG_Y),
+            (Vec3::Y, Vec3::Z),
+            (Vec3::NEG_Y, Vec3::NEG_Z),
+            (Vec3::Z, Vec3::NEG_Y),
+            (Vec3::NEG_Z, Vec3::NEG_Y),
+        ]
+        .map(|(dir, up)| {
+            let view = Mat4::look_at_rh(source, source + dir, up);
+            DepthMap::render(view, projection, settings.size, objects)
+        });
+        Self {
+            settings,
+            kind: ShadowKind::Cube {
+                source,
+                faces: Box::new(faces),
+            },
+        }
+    }
+
+    /// 以给定的视图和投影变换渲染单张阴影贴图，用于平行光和聚光灯
+    ///
+    /// 与 `transform::perspective` 一致，`projection` 变换后可见区域要满足 `w < 0`。
+    /// 平行光使用正交投影时可以取 `-transform::orthogonal(..)`
+    pub fn single(
+        view: Mat4,
+        projection: Mat4,
+        settings: ShadowSettings,
+        objects: &[&Object],
+    ) -> Self {
+        Self {
+            settings,
+            kind: ShadowKind::Single(DepthMap::render(view, projection, settings.size, objects)),
+        }
+    }
+
+    /// 按光源的类型选择合适的投影渲染阴影贴图
+    ///
+    /// 点光源和面光源（以其中心近似）使用立方体贴图；聚光灯以外锥为视域渲染单张，
+    /// 外锥过宽时改用立方体贴图；平行光用正交投影覆盖 `objects` 的包围球
+    pub fn for_light(light: &Light, settings: ShadowSettings, objects: &[&Object]) -> Self {
+        match *light {
+            Light::Point { source, .. } | Light::Area { center: source, .. } => {
+                Self::point(source, POINT_Z_NEAR, POINT_Z_FAR, settings, objects)
+            }
+            Light::Spot {
+                source,
+                direction,
+                outer,
+                ..
+            } if outer < 60. => {
+                let view = Mat4::look_at_rh(source, source + direction, up_for(direction));
+                let projection =
+                    transform::perspective(2. * outer + 5., 1., POINT_Z_NEAR, POINT_Z_FAR);
+                Self::single(view, projection, settings, objects)
+            }
+            Light::Spot { source, .. } => {
+                Self::point(source, POINT_Z_NEAR, POINT_Z_FAR, settings, objects)
+            }
+            Light::Directional { direction, .. } => {
+                let (center, radius) = bounding_sphere(objects);
+                let eye = center - direction * 2. * radius;
+                let view = Mat4::look_at_rh(eye, center, up_for(direction));
+                let (r, near, far) = (radius, -radius, -3. * radius);
+                let projection = -transform::orthogonal(-r, r, -r, r, far, near);
+                Self::single(view, projection, settings, objects)
+            }
+        }
+    }
+
+    pub fn settings(&self) -> &ShadowSettings {
+        &self.settings
+    }
+
+    /// 世界坐标 `point` 处受到光照的比例，0 为完全在阴影中，1 为完全不被遮挡
+    ///
+    /// `normal` 为该处的单位法线，用于法线偏移
+    pub fn visibility(&self, point: Vec3, normal: Vec3) -> f32 {
+        match &self.kind {
+            ShadowKind::Single(map) => map.visibility(point, normal, &self.settings),
+            ShadowKind::Cube { source, faces } => {
+                // 按绝对值最大的分量选择立方体的面
+                let d = point - *source;
+                let a = d.abs();
+                let face = if a.x >= a.y && a.x >= a.z {
+                    (d.x < 0.) as usize
+                } else if a.y >= a.z {
+                    2 + (d.y < 0.) as usize
+                } else {
+                    4 + (d.z < 0.) as usize
+                };
+                faces[face].visibility(point, normal, &self.settings)
+            }
+        }
+    }
+}
+
+/// 为每个光源渲染阴影贴图，结果与 `lights` 一一对应
+pub fn shadow_maps(
+    lights: &[Light],
+    settings: ShadowSettings,
+    objects: &[&Object],
+) -> Vec<Option<ShadowMap>> {
+    lights
+        .iter()
+        .map(|light| Some(ShadowMap::for_light(light, settings, objects)))
+        .collect()
+}
+
+/// 第 `light` 个光源在 `point` 处的可见比例，没有阴影贴图的光源总是可见
+#[inline]
+pub(crate) fn visibility(
+    shadow_maps: &[Option<ShadowMap>],
+    light: usize,
+    point: Vec3,
+    normal: Vec3,
+) -> f32 {
+    match shadow_maps.get(light) {
+        Some(Some(map)) => map.visibility(point, normal),
+        _ => 1.,
+    }
+}
+
+/// 与 `direction` 不平行的上方向
+fn up_for(direction: Vec3) -> Vec3 {
+    if direction.normalize().y.abs() > 0.99 {
+        Vec3::Z
+    } else {
+        Vec3::Y
+    }
+}
+
+/// 所有物体在世界坐标下的包围球（以包围盒的中心为球心）
+fn bounding_sphere(objects: &[&Object]) -> (Vec3, f32) {
+    let mut min = Vec3::splat(f32::INFINITY);
+    let mut max = Vec3::splat(f32::NEG_INFINITY);
+    for object in objects {
+        for &v in &object.vertices {
+            let p = object.model.transform_point3(v);
+            min = min.min(p);
+            max = max.max(p);
+        }
+    }
+    if min.x > max.x {
+        return (Vec3::ZERO, 1.);
+    }
+    ((min + max) * 0.5, ((max - min) * 0.5).length().max(1e-3))
+}
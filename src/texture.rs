@@ -1,32 +1,264 @@
-use glam::{vec3, Vec3};
+use glam::{vec2, vec3, Vec2, Vec3};
 use image::{DynamicImage, GenericImageView};
 
-#[derive(Debug)]
+use crate::color::{self, ColorSpace};
+
+/// 纹理的过滤方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Filter {
+    /// 取最近的纹素
+    #[default]
+    Nearest,
+    /// 在最精细的一级上对相邻四个纹素双线性插值
+    Bilinear,
+    /// 根据纹理坐标的导数选出相邻两级 mipmap，分别双线性插值后再线性混合
+    Trilinear,
+}
+
+/// 纹理坐标超出 `[0, 1]` 时的寻址方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Wrap {
+    /// 平铺重复
+    Repeat,
+    /// 平铺重复，每隔一次镜像翻转
+    MirroredRepeat,
+    /// 取边缘的纹素
+    #[default]
+    ClampToEdge,
+    /// 超出部分取采样器的边框颜色
+    ClampToBorder,
+}
+
+impl Wrap {
+    /// 将纹素下标映射到 `[0, len)`，返回 `None` 表示落在边框上
+    #[inline]
+    fn address(self, i: i64, len: usize) -> Option<usize> {
+        let len = len as i64;
+        let i = match self {
+            Wrap::Repeat => i.rem_euclid(len),
+            Wrap::MirroredRepeat => {
+                let i = i.rem_euclid(2 * len);
+                if i < len {
+                    i
+                } else {
+                    2 * len - 1 - i
+                }
+            }
+            Wrap::ClampToEdge => i.clamp(0, len - 1),
+            Wrap::ClampToBorder => {
+                if !(0..len).contains(&i) {
+                    return None;
+                }
+                i
+            }
+        };
+        Some(i as usize)
+    }
+}
+
+/// 采样器，决定着色器如何从纹理中取值
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Sampler {
+    pub filter: Filter,
+    /// 水平方向（u）的寻址方式
+    pub wrap_u: Wrap,
+    /// 竖直方向（v）的寻址方式
+    pub wrap_v: Wrap,
+    /// `Wrap::ClampToBorder` 时的边框颜色，按 bgr 排列
+    pub border: Vec3,
+}
+
+impl Sampler {
+    pub const fn new(filter: Filter) -> Self {
+        Self {
+            filter,
+            wrap_u: Wrap::ClampToEdge,
+            wrap_v: Wrap::ClampToEdge,
+            border: Vec3::ZERO,
+        }
+    }
+    pub const fn wrap(mut self, wrap: Wrap) -> Self {
+        self.wrap_u = wrap;
+        self.wrap_v = wrap;
+        self
+    }
+    pub const fn border(mut self, border: Vec3) -> Self {
+        self.border = border;
+        self
+    }
+}
+
+/// mipmap 中的一级，以左上为原点逐行存放颜色（bgr 顺序）
+#[derive(Debug, Clone)]
+struct MipLevel {
+    width: usize,
+    height: usize,
+    data: Vec<Vec3>,
+}
+
+impl MipLevel {
+    #[inline]
+    fn texel(&self, x: usize, y: usize) -> Vec3 {
+        self.data[y * self.width + x]
+    }
+
+    /// 按采样器的寻址方式取纹素，下标可以越界
+    #[inline]
+    fn fetch(&self, sampler: &Sampler, x: i64, y: i64) -> Vec3 {
+        match (
+            sampler.wrap_u.address(x, self.width),
+            sampler.wrap_v.address(y, self.height),
+        ) {
+            (Some(x), Some(y)) => self.texel(x, y),
+            _ => sampler.border,
+        }
+    }
+
+    /// 长宽各缩小一半，奇数长度时最后一行（列）与前一行（列）一起取平均
+    fn downsample(&self) -> Self {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let ys = 2 * y..(2 * y + 2 + (y == height - 1) as usize * (self.height % 2));
+            for x in 0..width {
+                let xs = 2 * x..(2 * x + 2 + (x == width - 1) as usize * (self.width % 2));
+                let mut sum = Vec3::ZERO;
+                let mut count = 0;
+                for sy in ys.clone().filter(|&sy| sy < self.height) {
+                    for sx in xs.clone().filter(|&sx| sx < self.width) {
+                        sum += self.texel(sx, sy);
+                        count += 1;
+                    }
+                }
+                data.push(sum / count as f32);
+            }
+        }
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// 取 `(x, y)` 所在的纹素，`x`、`y` 为以纹素为单位、以左上为原点的坐标
+    fn nearest(&self, sampler: &Sampler, x: f32, y: f32) -> Vec3 {
+        self.fetch(sampler, x.floor() as i64, y.floor() as i64)
+    }
+
+    /// 双线性插值，坐标的含义同 `nearest`
+    fn bilinear(&self, sampler: &Sampler, x: f32, y: f32) -> Vec3 {
+        // 纹素的中心位于 (i + 0.5, j + 0.5)
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = self
+            .fetch(sampler, x0, y0)
+            .lerp(self.fetch(sampler, x0 + 1, y0), fx);
+        let bottom = self
+            .fetch(sampler, x0, y0 + 1)
+            .lerp(self.fetch(sampler, x0 + 1, y0 + 1), fx);
+        top.lerp(bottom, fy)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Texture {
-    img: DynamicImage,
+    /// 第 0 级为原图，之后每一级长宽减半，直到 1x1
+    levels: Vec<MipLevel>,
 }
 
 impl Texture {
+    /// 以 sRGB 编码的颜色纹理创建，纹素会被解码为线性值
     pub fn new(img: DynamicImage) -> Self {
-        Self { img }
+        Self::with_color_space(img, ColorSpace::Srgb)
+    }
+    /// 以线性数据创建，适合高度图、法线贴图等非颜色纹理
+    pub fn linear(img: DynamicImage) -> Self {
+        Self::with_color_space(img, ColorSpace::Linear)
+    }
+    pub fn with_color_space(img: DynamicImage, space: ColorSpace) -> Self {
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let data = img
+            .pixels()
+            .map(|(_, _, p)| {
+                let p = p.0;
+                vec3(
+                    color::decode_u8(p[2], space),
+                    color::decode_u8(p[1], space),
+                    color::decode_u8(p[0], space),
+                )
+            })
+            .collect();
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            data,
+        }];
+        while let Some(last) = levels.last().filter(|l| l.width > 1 || l.height > 1) {
+            let next = last.downsample();
+            levels.push(next);
+        }
+        Self { levels }
     }
     pub fn width(&self) -> f32 {
-        self.img.width() as f32
+        self.levels[0].width as f32
     }
     pub fn height(&self) -> f32 {
-        self.img.height() as f32
+        self.levels[0].height as f32
+    }
+    /// mipmap 的级数
+    pub fn mip_levels(&self) -> usize {
+        self.levels.len()
     }
+    /// 以最近邻方式取 `(x, y)` 处的颜色，超出 `[0, 1]` 的坐标取边缘的纹素
     #[inline]
     pub fn pixel(&self, x: f32, y: f32) -> Vec3 {
-        // 传入的坐标是以左下为原点的，而 image 库以左上为原点
-        // 另外要记得限制 x、y 范围在 [0,1)
-        let x = (self.img.height() as f32 * x.clamp(0., 0.9999)) as u32;
-        let y = (self.img.width() as f32 * (1. - y).clamp(0., 0.9999)) as u32;
-        let p = self.img.get_pixel(x, y).0;
-        vec3(
-            (p[2] as f32) / 255.,
-            (p[1] as f32) / 255.,
-            (p[0] as f32) / 255.,
-        )
+        self.sample(&Sampler::default(), vec2(x, y), Vec2::ZERO, Vec2::ZERO)
+    }
+    /// 按采样器的设置在 `uv` 处采样
+    ///
+    /// `duv_dx`、`duv_dy` 是纹理坐标沿屏幕 x、y 方向移动一个像素时的变化量，用于选择 mipmap 级别
+    pub fn sample(&self, sampler: &Sampler, uv: Vec2, duv_dx: Vec2, duv_dy: Vec2) -> Vec3 {
+        // 传入的坐标是以左下为原点的，而纹理数据以左上为原点
+        let (u, v) = (uv.x, 1. - uv.y);
+        let scaled = |level: &MipLevel| (u * level.width as f32, v * level.height as f32);
+        let level0 = &self.levels[0];
+        match sampler.filter {
+            Filter::Nearest => {
+                let (x, y) = scaled(level0);
+                level0.nearest(sampler, x, y)
+            }
+            Filter::Bilinear => {
+                let (x, y) = scaled(level0);
+                level0.bilinear(sampler, x, y)
+            }
+            Filter::Trilinear => {
+                let lod = self.lod(duv_dx, duv_dy);
+                let lower = lod.floor() as usize;
+                let upper = (lower + 1).min(self.levels.len() - 1);
+                let fetch = |level: &MipLevel| {
+                    let (x, y) = scaled(level);
+                    level.bilinear(sampler, x, y)
+                };
+                let c = fetch(&self.levels[lower]);
+                let t = lod - lower as f32;
+                if t > 0. {
+                    c.lerp(fetch(&self.levels[upper]), t)
+                } else {
+                    c
+                }
+            }
+        }
+    }
+    /// 由屏幕空间的导数估计一个像素覆盖的纹素数，从而得到 mipmap 级别
+    fn lod(&self, duv_dx: Vec2, duv_dy: Vec2) -> f32 {
+        let size = Vec2::new(self.width(), self.height());
+        let rho = (duv_dx * size).length().max((duv_dy * size).length());
+        if !rho.is_finite() || rho <= 1. {
+            return 0.;
+        }
+        rho.log2().min((self.levels.len() - 1) as f32)
     }
 }
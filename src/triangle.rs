@@ -1,18 +1,14 @@
-use glam::{Vec2, Vec3, Vec4};
+use glam::Vec4;
 
-#[derive(Default, Debug)]
-pub struct Triangle {
+#[derive(Debug, Clone)]
+pub struct Triangle<V> {
     /// 三个顶点的齐次坐标，不保证顺序
     pub v: [Vec4; 3],
-    /// 三个顶点的颜色
-    pub color: [Vec3; 3],
-    /// 三个顶点对应的纹理坐标
-    pub texture: [Vec2; 3],
-    /// 三个顶点的法线
-    pub normal: [Vec3; 3],
+    /// 三个顶点的插值属性
+    pub varyings: [V; 3],
 }
 
-impl Triangle {
+impl<V> Triangle<V> {
     /// 返回三角形平面投影的最小包围盒
     ///
     /// 按 left, top, right bottom 顺序返回
@@ -24,19 +20,117 @@ impl Triangle {
         let bottom = self.v[0].y.min(self.v[1].y).min(self.v[2].y);
         (left, top, right, bottom)
     }
-    /// 计算 `(x,y)` 在三角形平面投影上的重心坐标表示
-    pub fn barycentric_coordinates(&self, x: f32, y: f32) -> (f32, f32, f32) {
+    /// 三角形平面投影的有向面积的两倍，逆时针为正
+    #[inline]
+    pub fn signed_area(&self) -> f32 {
         let v = &self.v;
-        let alpha = (-(x - v[1].x) * (v[2].y - v[1].y) + (y - v[1].y) * (v[2].x - v[1].x))
-            / (-(v[0].x - v[1].x) * (v[2].y - v[1].y) + (v[0].y - v[1].y) * (v[2].x - v[1].x));
-        let beta = (-(x - v[2].x) * (v[0].y - v[2].y) + (y - v[2].y) * (v[0].x - v[2].x))
-            / (-(v[1].x - v[2].x) * (v[0].y - v[2].y) + (v[1].y - v[2].y) * (v[0].x - v[2].x));
-        (alpha, beta, 1.0 - alpha - beta)
+        (v[1].x - v[0].x) * (v[2].y - v[0].y) - (v[2].x - v[0].x) * (v[1].y - v[0].y)
+    }
+}
+
+/// 屏幕坐标转为定点数时保留的亚像素精度位数
+pub const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+
+/// 以定点数表示的三角形三条边的边函数，用于增量地判断像素覆盖并计算重心坐标
+///
+/// 采用 top-left 填充规则，共享一条边的相邻三角形恰好各覆盖边上的像素一次
+pub struct EdgeFunctions {
+    /// 每移动一个像素，各边函数的增量
+    step_x: [i64; 3],
+    step_y: [i64; 3],
+    /// 各边的起点和终点，用于直接求边函数值
+    edges: [((i64, i64), (i64, i64)); 3],
+    /// top-left 规则的偏置。不是 top-left 的边，恰好落在边上的点不算在三角形内
+    bias: [i64; 3],
+    /// 三角形有向面积的两倍，统一为正
+    area: i64,
+    /// 第 i 条边所对的顶点在原三角形中的下标
+    order: [usize; 3],
+}
+
+impl EdgeFunctions {
+    /// 由屏幕坐标下的三角形构建边函数，坐标对齐到亚像素网格后退化的三角形返回 `None`
+    pub fn new<V>(t: &Triangle<V>) -> Option<Self> {
+        let fixed = t.v.map(|p| {
+            (
+                (p.x * SUBPIXEL_ONE as f32).round() as i64,
+                (p.y * SUBPIXEL_ONE as f32).round() as i64,
+            )
+        });
+        let area = edge_function(fixed[0], fixed[1], fixed[2]);
+        if area == 0 {
+            return None;
+        }
+        // 统一为逆时针顺序，这样三角形内部的边函数值都为正
+        let order = if area > 0 { [0, 1, 2] } else { [0, 2, 1] };
+        let v = order.map(|i| fixed[i]);
+        // 第 i 条边是第 i 个顶点所对的边
+        let edges = [(v[1], v[2]), (v[2], v[0]), (v[0], v[1])];
+        Some(Self {
+            step_x: edges.map(|(a, b)| -(b.1 - a.1) * SUBPIXEL_ONE),
+            step_y: edges.map(|(a, b)| (b.0 - a.0) * SUBPIXEL_ONE),
+            edges,
+            bias: edges.map(|(a, b)| {
+                let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+                // y 轴向上且逆时针时，向下走的是左边，水平向左走的是上边
+                if dy < 0 || (dy == 0 && dx < 0) {
+                    0
+                } else {
+                    -1
+                }
+            }),
+            area: area.abs(),
+            order,
+        })
     }
-    /// 根据重心坐标判断一个点是否在三角形内部
+    /// 像素 `(px, py)` 中心处的三个边函数值
     #[inline]
-    pub fn inside_triangle(alpha: f32, beta: f32, gamma: f32) -> bool {
-        // 重心坐标任意一个为负即说明在外部
-        alpha >= 0. && beta >= 0. && gamma >= 0.
+    pub fn evaluate(&self, px: usize, py: usize) -> [i64; 3] {
+        let x = ((px as i64) << SUBPIXEL_BITS) + SUBPIXEL_ONE / 2;
+        let y = ((py as i64) << SUBPIXEL_BITS) + SUBPIXEL_ONE / 2;
+        self.edges.map(|(a, b)| edge_function(a, b, (x, y)))
     }
+    /// 将边函数值移动到右侧一个像素
+    #[inline]
+    pub fn step_x(&self, e: &mut [i64; 3]) {
+        for (e, step) in e.iter_mut().zip(self.step_x) {
+            *e += step;
+        }
+    }
+    /// 将边函数值移动到上方一个像素
+    #[inline]
+    pub fn step_y(&self, e: &mut [i64; 3]) {
+        for (e, step) in e.iter_mut().zip(self.step_y) {
+            *e += step;
+        }
+    }
+    /// 将边函数值移动一个亚像素偏移，`dx`、`dy` 以 1/16 像素为单位
+    #[inline]
+    pub fn offset(&self, e: &[i64; 3], dx: i64, dy: i64) -> [i64; 3] {
+        let unit = SUBPIXEL_ONE / 16;
+        [0, 1, 2].map(|i| {
+            e[i] + (self.step_x[i] / SUBPIXEL_ONE * dx + self.step_y[i] / SUBPIXEL_ONE * dy) * unit
+        })
+    }
+    /// 根据边函数值判断该点是否被三角形覆盖
+    #[inline]
+    pub fn covers(&self, e: &[i64; 3]) -> bool {
+        e[0] + self.bias[0] >= 0 && e[1] + self.bias[1] >= 0 && e[2] + self.bias[2] >= 0
+    }
+    /// 由边函数值得到按原顶点顺序排列的重心坐标
+    #[inline]
+    pub fn barycentric(&self, e: &[i64; 3]) -> (f32, f32, f32) {
+        let mut w = [0.; 3];
+        for i in 0..3 {
+            w[self.order[i]] = e[i] as f32 / self.area as f32;
+        }
+        (w[0], w[1], w[2])
+    }
+}
+
+/// 点 `p` 相对于有向边 `a -> b` 的边函数，点在边左侧时为正
+#[inline]
+fn edge_function(a: (i64, i64), b: (i64, i64), p: (i64, i64)) -> i64 {
+    (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
 }
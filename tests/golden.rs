@@ -0,0 +1,1102 @@
+//! 金标准图像回归测试
+//!
+//! 用各个着色器渲染自带的模型，并与 `tests/golden` 下的参考图像逐像素比较。
+//! 比较失败时，实际结果和差异图像会写入 `target/golden-diff`。
+//!
+//! 有意修改渲染结果后，用 `UPDATE_GOLDEN=1 cargo test --test golden` 重新生成参考图像。
+
+use glam::{vec2, vec3, Mat4, Vec2, Vec3, Vec4};
+use image::{DynamicImage, Rgb, RgbImage};
+use lab_graphics::color::{ColorSpace, ToneMapping};
+use lab_graphics::environment::Environment;
+use lab_graphics::object::{LoadOptions, Material, NormalMode, Object};
+use lab_graphics::rasterizer::{
+    BlendMode, CullMode, FrontFace, Msaa, Rasterizer, RenderMode, ResolveFilter, Transparency,
+};
+use lab_graphics::shaders::{
+    standard_vertex, Attributes, BlinnPhongShader, BumpShader, DisplacementShader, EmptyShader,
+    Light, NormalMapFormat, NormalMapShader, NormalShader, Payload, PbrShader, Shader,
+    StandardShader, TextureShader, Uniforms, Vertex, VertexOutput,
+};
+use lab_graphics::shadow::ShadowSettings;
+use lab_graphics::texture::{Filter, Sampler, Texture, Wrap};
+use lab_graphics::transform;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const WIDTH: usize = 128;
+const HEIGHT: usize = 128;
+/// 单个通道允许的最大误差
+const CHANNEL_TOLERANCE: u8 = 2;
+/// 允许超出误差的像素比例，用于容忍不同平台浮点运算的细微差别
+const MAX_MISMATCH_RATIO: f64 = 0.001;
+
+const EYE_POS: Vec3 = Vec3::new(0., 1., 6.);
+const ANGLE_BETA: f32 = -10.;
+
+/// 加载模型，并摆放到大致填满画面的位置
+fn load_model(model: &str, texture: &str) -> Object {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let obj = format!("{root}/model/{model}.obj");
+    let texture = format!("{root}/model/{texture}");
+    let img = image::open(&texture).unwrap();
+    // 高度图不是颜色数据，按线性值读取
+    let texture = if texture.ends_with("hmap.jpg") {
+        Texture::linear(img)
+    } else {
+        Texture::new(img)
+    };
+    let object = Object::load_obj(&obj).unwrap().texture(texture);
+    let model = match model {
+        "spot_triangulated_good" => transform::model(0., 0., 0., 140., 2.5),
+        "cube" => transform::model(0., 0., 0., 30., 0.12),
+        "tetrahedron" => transform::model(0., -1.1, 2.2, 30., 0.08),
+        "bunny" => transform::model(0.3, -2.2, 0., 0., 20.),
+        _ => unreachable!(),
+    };
+    object.model(model)
+}
+
+fn rasterizer<S: Shader>(shader: S, msaa: Msaa, mode: RenderMode) -> Rasterizer<S> {
+    let mut rst = Rasterizer::with_msaa(WIDTH, HEIGHT, shader, msaa);
+    rst.view(transform::view(EYE_POS, 0., ANGLE_BETA))
+        .projection(transform::perspective(45., 1., 0.1, 50.))
+        .cull_mode(CullMode::Back)
+        .render_mode(mode);
+    rst
+}
+
+fn render<S: Shader>(shader: S, object: &Object) -> RgbImage {
+    let mut rst = rasterizer(shader, Msaa::Off, RenderMode::Serial);
+    rst.clear();
+    rst.draw(object);
+    rst.to_image()
+}
+
+/// 将 `actual` 与名为 `name` 的参考图像比较
+fn check(name: &str, actual: &RgbImage) {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let golden = root.join("tests/golden").join(format!("{name}.png"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(golden.parent().unwrap()).unwrap();
+        actual.save(&golden).unwrap();
+        return;
+    }
+    let expected = match image::open(&golden) {
+        Ok(img) => img.to_rgb8(),
+        Err(e) => panic!(
+            "无法读取参考图像 {}：{e}，可用 UPDATE_GOLDEN=1 生成",
+            golden.display()
+        ),
+    };
+    assert_eq!(
+        expected.dimensions(),
+        actual.dimensions(),
+        "{name} 图像大小不一致"
+    );
+
+    // 差异图像中，超出误差的像素标为红色，其余像素为参考图像的暗化版本
+    let mut diff = RgbImage::new(actual.width(), actual.height());
+    let mut mismatched = 0;
+    for ((e, a), d) in expected
+        .pixels()
+        .zip(actual.pixels())
+        .zip(diff.pixels_mut())
+    {
+        let over =
+            e.0.iter()
+                .zip(a.0)
+                .any(|(&e, a)| e.abs_diff(a) > CHANNEL_TOLERANCE);
+        *d = if over {
+            mismatched += 1;
+            Rgb([255, 0, 0])
+        } else {
+            Rgb(e.0.map(|c| c / 4))
+        };
+    }
+    let allowed = (MAX_MISMATCH_RATIO * (actual.width() * actual.height()) as f64) as usize;
+    if mismatched > allowed {
+        let out = root.join("target/golden-diff");
+        std::fs::create_dir_all(&out).unwrap();
+        actual.save(out.join(format!("{name}.actual.png"))).unwrap();
+        diff.save(out.join(format!("{name}.diff.png"))).unwrap();
+        panic!(
+            "{name} 有 {mismatched} 个像素与参考图像不一致（允许 {allowed} 个），结果已写入 {}",
+            out.display()
+        );
+    }
+}
+
+macro_rules! golden_tests {
+    ($($(#[$attr:meta])* $name:ident: $model:literal, $texture:literal, $shader:expr;)*) => {
+        $(
+            #[test]
+            $(#[$attr])*
+            fn $name() {
+                let object = load_model($model, $texture);
+                check(stringify!($name), &render($shader, &object));
+            }
+        )*
+    };
+}
+
+golden_tests! {
+    spot_blinn_phong: "spot_triangulated_good", "spot_texture.png", BlinnPhongShader::example(EYE_POS);
+    spot_bump: "spot_triangulated_good", "hmap.jpg", BumpShader::new(EYE_POS);
+    spot_displacement: "spot_triangulated_good", "hmap.jpg", DisplacementShader::example(EYE_POS);
+    spot_empty: "spot_triangulated_good", "spot_texture.png", EmptyShader;
+    spot_normal: "spot_triangulated_good", "spot_texture.png", NormalShader;
+    spot_pbr: "spot_triangulated_good", "spot_texture.png", PbrShader::example(EYE_POS);
+    spot_texture: "spot_triangulated_good", "spot_texture.png", TextureShader::example(EYE_POS);
+
+    cube_blinn_phong: "cube", "spot_texture.png", BlinnPhongShader::example(EYE_POS);
+    cube_bump: "cube", "hmap.jpg", BumpShader::new(EYE_POS);
+    cube_displacement: "cube", "hmap.jpg", DisplacementShader::example(EYE_POS);
+    cube_empty: "cube", "spot_texture.png", EmptyShader;
+    cube_normal: "cube", "spot_texture.png", NormalShader;
+    cube_pbr: "cube", "spot_texture.png", PbrShader::example(EYE_POS);
+    cube_texture: "cube", "spot_texture.png", TextureShader::example(EYE_POS);
+
+    tetrahedron_blinn_phong: "tetrahedron", "spot_texture.png", BlinnPhongShader::example(EYE_POS);
+    tetrahedron_bump: "tetrahedron", "hmap.jpg", BumpShader::new(EYE_POS);
+    tetrahedron_displacement: "tetrahedron", "hmap.jpg", DisplacementShader::example(EYE_POS);
+    tetrahedron_empty: "tetrahedron", "spot_texture.png", EmptyShader;
+    tetrahedron_normal: "tetrahedron", "spot_texture.png", NormalShader;
+    tetrahedron_texture: "tetrahedron", "spot_texture.png", TextureShader::example(EYE_POS);
+
+    bunny_blinn_phong: "bunny", "spot_texture.png", BlinnPhongShader::example(EYE_POS);
+    bunny_bump: "bunny", "hmap.jpg", BumpShader::new(EYE_POS);
+    bunny_displacement: "bunny", "hmap.jpg", DisplacementShader::example(EYE_POS);
+    bunny_empty: "bunny", "spot_texture.png", EmptyShader;
+    bunny_normal: "bunny", "spot_texture.png", NormalShader;
+    bunny_texture: "bunny", "spot_texture.png", TextureShader::example(EYE_POS);
+}
+
+/// 边缘按覆盖的采样点比例与背景混合
+#[test]
+fn spot_texture_msaa4() {
+    let object = load_model("spot_triangulated_good", "spot_texture.png");
+    let mut rst = rasterizer(
+        TextureShader::example(EYE_POS),
+        Msaa::X4,
+        RenderMode::Serial,
+    );
+    rst.clear();
+    rst.draw(&object);
+    check("spot_texture_msaa4", &rst.to_image());
+}
+
+#[test]
+fn cube_texture_msaa8() {
+    let object = load_model("cube", "spot_texture.png");
+    let mut rst = rasterizer(
+        TextureShader::example(EYE_POS),
+        Msaa::X8,
+        RenderMode::Serial,
+    );
+    rst.clear();
+    rst.draw(&object);
+    check("cube_texture_msaa8", &rst.to_image());
+}
+
+fn render_ssaa(filter: ResolveFilter, mode: RenderMode) -> Rasterizer<TextureShader> {
+    let object = load_model("cube", "spot_texture.png");
+    let mut rst = Rasterizer::with_ssaa(WIDTH, HEIGHT, TextureShader::example(EYE_POS), 3, filter);
+    rst.view(transform::view(EYE_POS, 0., ANGLE_BETA))
+        .projection(transform::perspective(45., 1., 0.1, 50.))
+        .cull_mode(CullMode::Back)
+        .render_mode(mode);
+    rst.clear();
+    rst.draw(&object);
+    rst
+}
+
+#[test]
+fn cube_ssaa_box() {
+    let rst = render_ssaa(ResolveFilter::Box, RenderMode::Serial);
+    check("cube_ssaa_box", &rst.to_image());
+}
+
+/// 帐篷滤波会混合相邻像素，边缘比盒式滤波更柔和
+#[test]
+fn cube_ssaa_tent() {
+    let rst = render_ssaa(ResolveFilter::Tent, RenderMode::Serial);
+    check("cube_ssaa_tent", &rst.to_image());
+}
+
+#[test]
+fn ssaa_tiled_matches_serial() {
+    for filter in [ResolveFilter::Box, ResolveFilter::Tent] {
+        let frames = [RenderMode::Serial, RenderMode::Tiled]
+            .map(|mode| render_ssaa(filter, mode).data().to_vec());
+        assert!(
+            frames[0] == frames[1],
+            "SSAA {filter:?} 下 tile 与单线程的结果不一致"
+        );
+    }
+}
+
+/// 保存的 PPM 和 PNG 文件与 `to_image` 的结果一致
+#[test]
+fn save_round_trip() {
+    let object = load_model("cube", "spot_texture.png");
+    let mut rst = Rasterizer::new(96, 64, TextureShader::example(EYE_POS));
+    rst.view(transform::view(EYE_POS, 0., ANGLE_BETA))
+        .projection(transform::perspective(45., 1.5, 0.1, 50.));
+    rst.clear();
+    rst.draw(&object);
+    let image = rst.to_image();
+    assert_eq!(image.dimensions(), (96, 64));
+
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let ppm = dir.join("save_round_trip.ppm");
+    rst.save_ppm(&ppm).unwrap();
+    let bytes = std::fs::read(&ppm).unwrap();
+    let header = b"P6\n96 64\n255\n";
+    assert_eq!(&bytes[..header.len()], header);
+    assert_eq!(bytes.len(), header.len() + 96 * 64 * 3);
+    assert!(bytes[header.len()..] == image.as_raw()[..]);
+
+    let png = dir.join("save_round_trip.png");
+    rst.save_png(&png).unwrap();
+    assert!(image::open(&png).unwrap().to_rgb8() == image);
+}
+
+/// 高光超出显示范围的部分由色调映射压缩，而不是直接截断
+#[test]
+fn spot_aces() {
+    let object = load_model("spot_triangulated_good", "spot_texture.png");
+    let mut rst = rasterizer(
+        TextureShader::example(EYE_POS),
+        Msaa::Off,
+        RenderMode::Serial,
+    );
+    rst.tone_mapping(ToneMapping::Aces).exposure(1.);
+    rst.clear();
+    rst.draw(&object);
+    check("spot_aces", &rst.to_image());
+}
+
+/// 不使用纹理的金色金属
+#[test]
+fn spot_pbr_gold() {
+    let object = load_model("spot_triangulated_good", "spot_texture.png");
+    let mut shader = PbrShader::example(EYE_POS);
+    // bgr 顺序
+    shader
+        .base_color(Vec3::new(0.336, 0.766, 1.))
+        .use_texture(false)
+        .metallic(1.)
+        .roughness(0.3);
+    check("spot_pbr_gold", &render(shader, &object));
+}
+
+/// 只由环境贴图照亮的金属，背景为天空盒
+#[test]
+fn spot_ibl() {
+    let object = load_model("spot_triangulated_good", "spot_texture.png");
+    let sky = concat!(env!("CARGO_MANIFEST_DIR"), "/model/sky.hdr");
+    let environment = Arc::new(Environment::open(sky).unwrap());
+    let mut shader = PbrShader::example(EYE_POS);
+    shader.lights.clear();
+    shader
+        .environment(environment.clone())
+        .base_color(Vec3::new(0.336, 0.766, 1.))
+        .use_texture(false)
+        .metallic(1.)
+        .roughness(0.3);
+    let mut rst = rasterizer(shader, Msaa::Off, RenderMode::Serial);
+    rst.tone_mapping(ToneMapping::Aces);
+    rst.clear();
+    rst.draw_skybox(&environment);
+    rst.draw(&object);
+    check("spot_ibl", &rst.to_image());
+}
+
+/// 一个 .obj 中的两个网格分别使用 .mtl 中带贴图的材质和纯色材质
+#[test]
+fn boxes_materials() {
+    let obj = concat!(env!("CARGO_MANIFEST_DIR"), "/model/boxes.obj");
+    let (objects, material_error) = Object::load_all(obj).unwrap();
+    assert!(material_error.is_none());
+    let names: Vec<_> = objects
+        .iter()
+        .map(|o| o.material.as_ref().unwrap().name.as_str())
+        .collect();
+    assert_eq!(names, ["textured", "red"]);
+    let textured = objects[0].material.as_ref().unwrap();
+    assert!(objects[0].texture.is_some() && textured.specular_map.is_none());
+    // 纯色材质带有 map_Ks 高光贴图和 norm 指定的法线贴图，贴图名前有选项，文件名中有空格
+    let red = objects[1].material.as_ref().unwrap();
+    assert!(objects[1].texture.is_none() && red.specular_map.is_some());
+    // 法线贴图按线性数据读取，bgr 顺序
+    let normal_map = red.normal_map.as_ref().unwrap();
+    assert_close(
+        normal_map.pixel(0.5, 0.5),
+        vec3(1., 128. / 255., 128. / 255.),
+    );
+    let mut rst = rasterizer(
+        TextureShader::example(EYE_POS),
+        Msaa::Off,
+        RenderMode::Serial,
+    );
+    rst.clear();
+    for object in objects {
+        rst.draw(&object.model(transform::model(0., 0., 0., 30., 1.)));
+    }
+    check("boxes_materials", &rst.to_image());
+}
+
+/// 单独读取第一个网格时同样把四边形三角化
+#[test]
+fn load_obj_triangulates() {
+    let obj = concat!(env!("CARGO_MANIFEST_DIR"), "/model/boxes.obj");
+    let object = Object::load_obj(obj).unwrap();
+    assert_eq!(object.indices.len(), 12);
+    assert_eq!(object.indices, Object::load_all(obj).unwrap().0[0].indices);
+}
+
+/// 找不到 .mtl 时网格照常读取且不带材质，错误交给调用者
+#[test]
+fn missing_material() {
+    let obj =
+        std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/model/boxes.obj")).unwrap();
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("missing_material.obj");
+    std::fs::write(&path, obj.replace("mtllib boxes.mtl", "mtllib missing.mtl")).unwrap();
+    let (objects, material_error) = Object::load_all(&path).unwrap();
+    assert!(material_error.is_some());
+    assert_eq!(objects.len(), 2);
+    assert!(objects
+        .iter()
+        .all(|o| o.material.is_none() && o.texture.is_none()));
+}
+
+/// 没有纹理坐标时不采样高光贴图，结果与材质没有高光贴图时相同
+#[test]
+fn specular_map_without_texcoords() {
+    let obj = concat!(env!("CARGO_MANIFEST_DIR"), "/model/boxes.obj");
+    let mut object = Object::load_all(obj).unwrap().0.remove(1);
+    object.texcoords.clear();
+    object.texcoord_indices.clear();
+    object.generate_tangents();
+    object.model = transform::model(0., 0., 0., 30., 1.);
+    let render = |object: &Object| {
+        let mut rst = rasterizer(
+            TextureShader::example(EYE_POS),
+            Msaa::Off,
+            RenderMode::Serial,
+        );
+        rst.clear();
+        rst.draw(object);
+        rst.data().to_vec()
+    };
+    let with_map = render(&object);
+    let m = object.material.take().unwrap();
+    assert!(m.specular_map.is_some());
+    object.material = Some(Arc::new(Material {
+        name: m.name.clone(),
+        ambient: m.ambient,
+        diffuse: m.diffuse,
+        specular: m.specular,
+        shininess: m.shininess,
+        dissolve: m.dissolve,
+        specular_map: None,
+        normal_map: None,
+    }));
+    assert!(with_map == render(&object));
+}
+
+/// 半透明的奶牛叠在不透明的立方体前面
+fn render_transparent(
+    blend_mode: BlendMode,
+    transparency: Transparency,
+    mode: RenderMode,
+) -> Rasterizer<TextureShader> {
+    let cube =
+        load_model("cube", "spot_texture.png").model(transform::model(0.8, 0.3, -2., 30., 0.15));
+    let spot = load_model("spot_triangulated_good", "spot_texture.png");
+    let mut shader = TextureShader::example(EYE_POS);
+    shader.opacity(0.5);
+    let mut rst = rasterizer(shader, Msaa::X4, mode);
+    rst.blend_mode(blend_mode)
+        .transparency(transparency)
+        .cull_mode(CullMode::None);
+    rst.clear();
+    rst.draw(&cube);
+    rst.draw_transparent(&[&spot]);
+    rst
+}
+
+#[test]
+fn spot_transparent_alpha() {
+    let rst = render_transparent(BlendMode::Alpha, Transparency::Sorted, RenderMode::Serial);
+    check("spot_transparent_alpha", &rst.to_image());
+}
+
+#[test]
+fn spot_transparent_additive() {
+    let rst = render_transparent(
+        BlendMode::Additive,
+        Transparency::Sorted,
+        RenderMode::Serial,
+    );
+    check("spot_transparent_additive", &rst.to_image());
+}
+
+/// 奶牛自身前后重叠的部分由 A-buffer 逐采样点排序
+#[test]
+fn spot_transparent_abuffer() {
+    let rst = render_transparent(
+        BlendMode::Alpha,
+        Transparency::ABuffer { budget: 4 },
+        RenderMode::Serial,
+    );
+    assert_eq!(rst.fragment_overflow(), 0);
+    check("spot_transparent_abuffer", &rst.to_image());
+}
+
+/// 预算不足时多出的片元直接混合，结果仍与 tile 划分方式无关
+#[test]
+fn abuffer_overflow() {
+    let frames = [RenderMode::Serial, RenderMode::Tiled].map(|mode| {
+        let rst = render_transparent(BlendMode::Alpha, Transparency::ABuffer { budget: 1 }, mode);
+        assert!(rst.fragment_overflow() > 0);
+        rst.data().to_vec()
+    });
+    assert!(
+        frames[0] == frames[1],
+        "A-buffer 溢出时 tile 与单线程的结果不一致"
+    );
+}
+
+/// 以顶点颜色和固定的 alpha 输出的着色器
+struct VertexColorShader(f32);
+
+impl StandardShader for VertexColorShader {
+    fn shading(&self, payload: Payload<Attributes>) -> Vec4 {
+        payload.varyings.color.extend(self.0)
+    }
+}
+
+/// 两个互相穿插的半透明四边形，交线两侧的前后关系相反
+fn render_intersecting(transparency: Transparency) -> Rasterizer<VertexColorShader> {
+    const SIZE: usize = 64;
+    let quad = |y0: f32, y1: f32, z0: f32, z1: f32, color: Vec3| {
+        let mut object = flat_object(
+            vec![
+                Vec3::new(8., y0, z0),
+                Vec3::new(56., y0, z1),
+                Vec3::new(56., y1, z1),
+                Vec3::new(8., y1, z0),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+        );
+        object.vertex_color = vec![color; 4];
+        object
+    };
+    // 在 x = 32 处相交，重叠部分左侧红色在前，右侧蓝色在前
+    let red = quad(8., 40., -0.5, -1.5, Vec3::Z);
+    let blue = quad(24., 56., -1.5, -0.5, Vec3::X);
+    let mut rst = Rasterizer::new(SIZE, SIZE, VertexColorShader(0.5));
+    let size = SIZE as f32;
+    rst.projection(-transform::orthogonal(0., size, 0., size, -2., 0.))
+        .blend_mode(BlendMode::Alpha)
+        .transparency(transparency)
+        .output_color_space(ColorSpace::Linear);
+    rst.clear();
+    rst.draw_transparent(&[&red, &blue]);
+    rst
+}
+
+/// 按物体、三角形排序无法处理互相穿插的面，A-buffer 逐采样点排序后两侧都正确
+#[test]
+fn intersecting_abuffer() {
+    let abuffer = render_intersecting(Transparency::ABuffer { budget: 4 });
+    assert_eq!(abuffer.fragment_overflow(), 0);
+    check("intersecting_abuffer", &abuffer.to_image());
+    let sorted = render_intersecting(Transparency::Sorted);
+    assert!(
+        abuffer.data() != sorted.data(),
+        "A-buffer 与按三角形排序的结果相同"
+    );
+}
+
+/// 奶牛站在压扁的立方体上，两个点光源都投下阴影
+#[test]
+fn spot_shadow() {
+    let spot = load_model("spot_triangulated_good", "spot_texture.png");
+    let ground = load_model("cube", "spot_texture.png")
+        .model(transform::tranlation(0., -1.3, 0.) * transform::scaling(0.3, 0.005, 0.3));
+    let mut shader = BlinnPhongShader::example(EYE_POS);
+    let settings = ShadowSettings {
+        size: 256,
+        ..Default::default()
+    };
+    shader.cast_shadows(&[&spot, &ground], settings);
+    let mut rst = rasterizer(shader, Msaa::Off, RenderMode::Serial);
+    rst.clear();
+    rst.draw(&spot);
+    rst.draw(&ground);
+    check("spot_shadow", &rst.to_image());
+}
+
+/// 平行光、聚光灯和面光源各自投下阴影
+#[test]
+fn spot_light_types() {
+    let spot = load_model("spot_triangulated_good", "spot_texture.png");
+    let ground = load_model("cube", "spot_texture.png")
+        .model(transform::tranlation(0., -1.3, 0.) * transform::scaling(0.3, 0.005, 0.3));
+    let mut shader = TextureShader::example(EYE_POS);
+    shader.lights = vec![
+        Light::directional(Vec3::new(-1., -2., -0.5), Vec3::splat(0.4)),
+        Light::spot(
+            Vec3::new(2., 5., 2.),
+            Vec3::new(-2., -5., -2.),
+            Vec3::splat(20.),
+            15.,
+            25.,
+        ),
+        Light::area(
+            Vec3::new(-1., 4., 1.),
+            Vec3::new(1., 0., 0.),
+            Vec3::new(0., 0., 1.),
+            Vec3::splat(10.),
+            4,
+        ),
+    ];
+    let settings = ShadowSettings {
+        size: 256,
+        ..Default::default()
+    };
+    shader.cast_shadows(&[&spot, &ground], settings);
+    let mut rst = rasterizer(shader, Msaa::Off, RenderMode::Serial);
+    rst.clear();
+    rst.draw(&spot);
+    rst.draw(&ground);
+    check("spot_light_types", &rst.to_image());
+}
+
+/// 立方体投影到地面上。重新生成的面法线与文件中的法线朝向一致，法线偏移后的阴影也相同
+#[test]
+fn cube_shadow() {
+    let ground = load_model("cube", "spot_texture.png")
+        .model(transform::tranlation(0., -1.3, 0.) * transform::scaling(0.3, 0.005, 0.3));
+    for generate in [false, true] {
+        let mut cube =
+            load_model("cube", "spot_texture.png").model(transform::model(0., -0.5, 0., 30., 0.08));
+        if generate {
+            cube.generate_normals(NormalMode::Flat);
+        }
+        let mut shader = BlinnPhongShader::example(EYE_POS);
+        // 不加深度偏移，完全靠法线偏移避免自遮挡
+        let settings = ShadowSettings {
+            size: 256,
+            bias: 0.,
+            ..Default::default()
+        };
+        shader.cast_shadows(&[&cube, &ground], settings);
+        let mut rst = rasterizer(shader, Msaa::Off, RenderMode::Serial);
+        rst.clear();
+        rst.draw(&cube);
+        rst.draw(&ground);
+        check("cube_shadow", &rst.to_image());
+    }
+}
+
+/// 没有法线的模型生成平滑法线
+#[test]
+fn bunny_smooth_normals() {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let options = LoadOptions {
+        normals: NormalMode::Smooth { crease_angle: 60. },
+    };
+    let object = Object::load_obj_with(format!("{root}/model/bunny.obj"), &options)
+        .unwrap()
+        .model(transform::model(0.3, -2.2, 0., 0., 20.));
+    check(
+        "bunny_smooth_normals",
+        &render(BlinnPhongShader::example(EYE_POS), &object),
+    );
+}
+
+/// 立方体的棱超过折痕角，重新生成的平滑法线与文件中的面法线结果相同
+#[test]
+fn cube_crease_angle() {
+    let mut object = load_model("cube", "spot_texture.png");
+    object.generate_normals(NormalMode::Smooth { crease_angle: 60. });
+    check(
+        "cube_blinn_phong",
+        &render(BlinnPhongShader::example(EYE_POS), &object),
+    );
+}
+
+/// 折痕角为 0 时每个面都不与相邻的面平均，平滑法线与面法线相同，朝向也一致
+#[test]
+fn flat_matches_zero_crease() {
+    let obj = concat!(env!("CARGO_MANIFEST_DIR"), "/model/cube.obj");
+    let corner_normals = |mode: NormalMode| {
+        let mut object = Object::load_obj(obj).unwrap();
+        object.generate_normals(mode);
+        object
+            .normal_indices
+            .iter()
+            .flatten()
+            .map(|&i| object.normals[i])
+            .collect::<Vec<_>>()
+    };
+    let flat = corner_normals(NormalMode::Flat);
+    let smooth = corner_normals(NormalMode::Smooth { crease_angle: 0. });
+    assert_eq!(flat.len(), smooth.len());
+    for (f, s) in flat.into_iter().zip(smooth) {
+        assert_close(f, s);
+    }
+}
+
+/// 由正弦起伏生成的 OpenGL 格式法线贴图，`flip_green` 时转换为 DirectX 格式
+fn wavy_normal_map(flip_green: bool) -> Texture {
+    let size = 64;
+    let img = RgbImage::from_fn(size, size, |x, y| {
+        // 图像的第一行对应 v = 1
+        let u = (x as f32 + 0.5) / size as f32;
+        let v = 1. - (y as f32 + 0.5) / size as f32;
+        let k = 4. * std::f32::consts::TAU;
+        let dh_du = (k * u).cos() * (k * v).sin();
+        let dh_dv = (k * u).sin() * (k * v).cos();
+        let n = Vec3::new(-0.5 * dh_du, -0.5 * dh_dv, 1.).normalize();
+        let g = if flip_green { -n.y } else { n.y };
+        Rgb([n.x, g, n.z].map(|c| ((c * 0.5 + 0.5) * 255.).round() as u8))
+    });
+    Texture::linear(DynamicImage::ImageRgb8(img))
+}
+
+#[test]
+fn cube_normal_map() {
+    let object = load_model("cube", "spot_texture.png");
+    let mut shader = NormalMapShader::example(EYE_POS);
+    shader.normal_map(wavy_normal_map(false));
+    check("cube_normal_map", &render(shader, &object));
+}
+
+/// 翻转 g 通道并按 DirectX 格式解码，结果与 OpenGL 格式相同
+#[test]
+fn cube_normal_map_directx() {
+    let object = load_model("cube", "spot_texture.png");
+    let mut shader = NormalMapShader::example(EYE_POS);
+    shader
+        .normal_map(wavy_normal_map(true))
+        .format(NormalMapFormat::DirectX);
+    check("cube_normal_map", &render(shader, &object));
+}
+
+/// 模型几乎贴着视点，近平面切开了其中的三角形
+#[test]
+fn spot_near_clip() {
+    let object = load_model("spot_triangulated_good", "spot_texture.png")
+        .model(transform::model(0., 0.6, 4.4, 140., 2.5));
+    check(
+        "spot_near_clip",
+        &render(TextureShader::example(EYE_POS), &object),
+    );
+}
+
+/// 视点位于立方体内部，所有面都与视锥相交
+#[test]
+fn cube_inside_clip() {
+    let object = load_model("cube", "spot_texture.png")
+        .model(transform::model(EYE_POS.x, EYE_POS.y, EYE_POS.z, 30., 0.12));
+    let mut rst = rasterizer(
+        TextureShader::example(EYE_POS),
+        Msaa::Off,
+        RenderMode::Serial,
+    );
+    rst.cull_mode(CullMode::None);
+    rst.clear();
+    rst.draw(&object);
+    check("cube_inside_clip", &rst.to_image());
+}
+
+/// 立方体超出画面的左上角，被视锥的侧面裁剪
+#[test]
+fn cube_border_clip() {
+    let object =
+        load_model("cube", "spot_texture.png").model(transform::model(-2., 1.8, 0., 30., 0.12));
+    check(
+        "cube_border_clip",
+        &render(TextureShader::example(EYE_POS), &object),
+    );
+}
+
+/// 剔除正面时只能看到立方体内侧的背面
+#[test]
+fn cube_cull_front() {
+    let object = load_model("cube", "spot_texture.png");
+    let mut rst = rasterizer(
+        TextureShader::example(EYE_POS),
+        Msaa::Off,
+        RenderMode::Serial,
+    );
+    rst.cull_mode(CullMode::Front);
+    rst.clear();
+    rst.draw(&object);
+    check("cube_cull_front", &rst.to_image());
+}
+
+/// 不剔除时背面先绘制也会被深度测试挡住，与剔除背面的结果相同；
+/// 把顺时针视为正面后，剔除正面等价于原来的剔除背面
+#[test]
+fn cube_cull_modes() {
+    let object = load_model("cube", "spot_texture.png");
+    for (cull_mode, front_face) in [
+        (CullMode::Back, FrontFace::Ccw),
+        (CullMode::None, FrontFace::Ccw),
+        (CullMode::Front, FrontFace::Cw),
+    ] {
+        let mut rst = rasterizer(
+            TextureShader::example(EYE_POS),
+            Msaa::Off,
+            RenderMode::Serial,
+        );
+        rst.cull_mode(cull_mode).front_face(front_face);
+        rst.clear();
+        rst.draw(&object);
+        check("cube_texture", &rst.to_image());
+    }
+}
+
+/// 由顶点和三角形构成的平面物体，所有顶点共用 +z 法线
+fn flat_object(vertices: Vec<Vec3>, indices: Vec<[usize; 3]>) -> Object {
+    Object {
+        vertices,
+        vertex_color: Vec::new(),
+        normals: vec![Vec3::Z],
+        texcoords: Vec::new(),
+        normal_indices: vec![[0; 3]; indices.len()],
+        indices,
+        texcoord_indices: Vec::new(),
+        tangents: Vec::new(),
+        tangent_indices: Vec::new(),
+        model: Mat4::IDENTITY,
+        texture: None,
+        material: None,
+    }
+}
+
+/// 统计片元着色器被调用的次数
+struct CountingShader(AtomicUsize);
+
+impl StandardShader for CountingShader {
+    fn shading(&self, _payload: Payload<Attributes>) -> Vec4 {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        Vec4::ONE
+    }
+}
+
+/// 面积为 0 的三角形不产生任何片元
+#[test]
+fn degenerate_triangles() {
+    let vertices = vec![
+        Vec3::new(-1., -1., 0.),
+        Vec3::new(1., 1., 0.),
+        Vec3::new(0., 0., 0.),
+        Vec3::new(1., -1., 0.),
+    ];
+    // 三点共线、两点重合、三点重合
+    let object = flat_object(vertices, vec![[0, 1, 2], [0, 3, 3], [1, 1, 1]])
+        .model(transform::model(0., 1., 0., 0., 1.));
+    for mode in [RenderMode::Serial, RenderMode::Tiled] {
+        let mut rst = rasterizer(CountingShader(AtomicUsize::new(0)), Msaa::X4, mode);
+        rst.cull_mode(CullMode::None);
+        rst.clear();
+        rst.draw(&object);
+        assert_eq!(rst.shader.0.load(Ordering::Relaxed), 0, "{mode:?}");
+    }
+    // 同样的顶点组成正常的三角形时会产生片元
+    let object = flat_object(object.vertices, vec![[0, 3, 1]]).model(object.model);
+    let mut rst = rasterizer(
+        CountingShader(AtomicUsize::new(0)),
+        Msaa::Off,
+        RenderMode::Serial,
+    );
+    rst.cull_mode(CullMode::None);
+    rst.clear();
+    rst.draw(&object);
+    assert!(rst.shader.0.load(Ordering::Relaxed) > 0);
+}
+
+/// 输出固定颜色的着色器
+struct ConstantShader(Vec4);
+
+impl StandardShader for ConstantShader {
+    fn shading(&self, _payload: Payload<Attributes>) -> Vec4 {
+        self.0
+    }
+}
+
+/// 共享边的三角形叠加绘制时，按 top-left 规则每个像素恰好被覆盖一次，既没有缝隙也没有重复
+#[test]
+fn shared_edges_cover_once() {
+    const SIZE: usize = 32;
+    let (lo, hi, mid) = (2., 30., 16.5);
+    let v = |x: f32, y: f32| Vec3::new(x, y, -1.);
+    // 对角线恰好穿过像素中心的两个三角形
+    let quad = flat_object(
+        vec![v(lo, lo), v(hi, lo), v(hi, hi), v(lo, hi)],
+        vec![[0, 1, 2], [0, 2, 3]],
+    );
+    // 围绕像素中心的 8 个三角形组成的扇形，拼成同一个正方形
+    let ring = [
+        v(lo, lo),
+        v(mid, lo),
+        v(hi, lo),
+        v(hi, mid),
+        v(hi, hi),
+        v(mid, hi),
+        v(lo, hi),
+        v(lo, mid),
+    ];
+    let fan = flat_object(
+        std::iter::once(v(mid, mid)).chain(ring).collect(),
+        (1..=8).map(|i| [0, i, i % 8 + 1]).collect(),
+    );
+    for (name, object) in [("quad", quad), ("fan", fan)] {
+        for mode in [RenderMode::Serial, RenderMode::Tiled] {
+            let mut rst =
+                Rasterizer::new(SIZE, SIZE, ConstantShader(Vec4::new(0.25, 0.25, 0.25, 1.)));
+            // 正交投影使世界坐标与屏幕坐标一致
+            let size = SIZE as f32;
+            rst.projection(-transform::orthogonal(0., size, 0., size, -2., 0.))
+                .blend_mode(BlendMode::Additive)
+                .output_color_space(ColorSpace::Linear)
+                .render_mode(mode);
+            rst.clear();
+            rst.draw_transparent(&[&object]);
+            let image = rst.to_image();
+            for (x, y, p) in image.enumerate_pixels() {
+                let y = SIZE as u32 - 1 - y;
+                let inside = (2..30).contains(&x) && (2..30).contains(&y);
+                let expected = if inside { 64 } else { 0 };
+                assert_eq!(p.0[0], expected, "{name} {mode:?} 像素 ({x}, {y})");
+            }
+        }
+    }
+}
+
+/// 纹理和输出都不做 gamma 处理时，结果与引入色彩空间之前一致
+#[test]
+fn spot_texture_legacy() {
+    let mut object = load_model("spot_triangulated_good", "spot_texture.png");
+    let texture = concat!(env!("CARGO_MANIFEST_DIR"), "/model/spot_texture.png");
+    object.texture = Some(Texture::linear(image::open(texture).unwrap()));
+    let mut rst = rasterizer(
+        TextureShader::example(EYE_POS),
+        Msaa::Off,
+        RenderMode::Serial,
+    );
+    rst.output_color_space(ColorSpace::Linear);
+    rst.clear();
+    rst.draw(&object);
+    check("spot_texture_legacy", &rst.to_image());
+}
+
+/// 在顶点着色器中沿法线方向把模型“吹胀”
+struct InflateShader;
+
+impl Shader for InflateShader {
+    type Varyings = Attributes;
+
+    fn vertex(&self, vertex: &Vertex, uniforms: &Uniforms) -> VertexOutput<Attributes> {
+        let vertex = Vertex {
+            position: vertex.position + 0.05 * vertex.normal,
+            ..*vertex
+        };
+        standard_vertex(&vertex, uniforms)
+    }
+    fn shading(&self, payload: Payload<Attributes>) -> Vec4 {
+        Shader::shading(&NormalShader, payload)
+    }
+}
+
+#[test]
+fn spot_inflated() {
+    let object = load_model("spot_triangulated_good", "spot_texture.png");
+    check("spot_inflated", &render(InflateShader, &object));
+}
+
+/// 只传递世界坐标作为插值属性，按坐标着色
+struct PositionShader;
+
+impl Shader for PositionShader {
+    type Varyings = Vec3;
+
+    fn vertex(&self, vertex: &Vertex, uniforms: &Uniforms) -> VertexOutput<Vec3> {
+        let point = uniforms.model * vertex.position.extend(1.);
+        VertexOutput {
+            position: uniforms.projection * uniforms.view * point,
+            varyings: point.truncate(),
+        }
+    }
+    fn shading(&self, payload: Payload<Vec3>) -> Vec4 {
+        (payload.varyings * 0.5 + 0.5).extend(1.)
+    }
+}
+
+#[test]
+fn spot_position() {
+    let object = load_model("spot_triangulated_good", "spot_texture.png");
+    check("spot_position", &render(PositionShader, &object));
+}
+
+/// 分 tile 并行光栅化的结果必须与单线程的参考实现逐位一致
+#[test]
+fn tiled_matches_serial() {
+    let object = load_model("spot_triangulated_good", "spot_texture.png");
+    for msaa in [Msaa::Off, Msaa::X4] {
+        let frames = [RenderMode::Serial, RenderMode::Tiled].map(|mode| {
+            let mut rst = rasterizer(TextureShader::example(EYE_POS), msaa, mode);
+            rst.clear();
+            rst.draw(&object);
+            rst.data().to_vec()
+        });
+        assert!(
+            frames[0] == frames[1],
+            "{msaa:?} 下 tile 与单线程的结果不一致"
+        );
+    }
+    for transparency in [Transparency::Sorted, Transparency::ABuffer { budget: 4 }] {
+        let frames = [RenderMode::Serial, RenderMode::Tiled].map(|mode| {
+            render_transparent(BlendMode::Alpha, transparency, mode)
+                .data()
+                .to_vec()
+        });
+        assert!(
+            frames[0] == frames[1],
+            "{transparency:?} 下 tile 与单线程的结果不一致"
+        );
+    }
+}
+
+/// 4x4 的线性纹理，r 通道为 `(x + 4y) * 16`，g、b 通道为 0
+fn mip_texture() -> Texture {
+    let img = RgbImage::from_fn(4, 4, |x, y| Rgb([((x + 4 * y) * 16) as u8, 0, 0]));
+    Texture::linear(DynamicImage::ImageRgb8(img))
+}
+
+fn assert_close(actual: Vec3, expected: Vec3) {
+    assert!(
+        actual.abs_diff_eq(expected, 1e-5),
+        "期望 {expected}，实际 {actual}"
+    );
+}
+
+/// 4x4 的纹理应生成 4x4、2x2、1x1 三级，每一级是上一级 2x2 块的平均
+#[test]
+fn mip_chain() {
+    let texture = mip_texture();
+    assert_eq!(texture.mip_levels(), 3);
+    let sampler = Sampler::new(Filter::Trilinear);
+    // 纹理宽 4，导数为 2^lod / 4 时正好落在第 lod 级上
+    let at_level = |lod: i32, uv: Vec2| {
+        let d = vec2(2f32.powi(lod) / 4., 0.);
+        texture.sample(&sampler, uv, d, Vec2::ZERO)
+    };
+    // 第 0 级就是原图
+    assert_close(at_level(0, vec2(0.375, 0.625)), vec3(0., 0., 80. / 255.));
+    // 第 1 级各纹素的中心，以左下为原点；数值为对应 2x2 块 r 通道的平均
+    for (uv, r) in [
+        (vec2(0.25, 0.75), 40.),
+        (vec2(0.75, 0.75), 72.),
+        (vec2(0.25, 0.25), 168.),
+        (vec2(0.75, 0.25), 200.),
+    ] {
+        assert_close(at_level(1, uv), vec3(0., 0., r / 255.));
+    }
+    // 最后一级是整张图的平均
+    for uv in [vec2(0.1, 0.9), vec2(0.5, 0.5), vec2(0.9, 0.1)] {
+        assert_close(at_level(2, uv), vec3(0., 0., 120. / 255.));
+    }
+}
+
+/// 三线性过滤在非整数级别上按小数部分混合相邻两级
+#[test]
+fn trilinear_blends_levels() {
+    let texture = mip_texture();
+    let sampler = Sampler::new(Filter::Trilinear);
+    let uv = vec2(0.25, 0.75);
+    // rho = 2^1.5，对应 lod = 1.5，第 1 级为 40，第 2 级为 120
+    let d = vec2(2f32.powf(1.5) / 4., 0.);
+    assert_close(
+        texture.sample(&sampler, uv, d, Vec2::ZERO),
+        vec3(0., 0., 80. / 255.),
+    );
+    // 沿 y 方向的导数同样参与级别的选择
+    assert_close(
+        texture.sample(&sampler, uv, Vec2::ZERO, d),
+        vec3(0., 0., 80. / 255.),
+    );
+    // 一个像素覆盖不到一个纹素时只用第 0 级，与双线性过滤一致
+    let bilinear = texture.sample(&Sampler::new(Filter::Bilinear), uv, Vec2::ZERO, Vec2::ZERO);
+    assert_close(
+        texture.sample(&sampler, uv, vec2(0.1, 0.), Vec2::ZERO),
+        bilinear,
+    );
+}
+
+/// 4x2 的纹理在各种寻址方式下，越界的纹理坐标应取到预期的纹素
+#[test]
+fn wrap_modes() {
+    let (width, height) = (4, 2);
+    let img = RgbImage::from_fn(width, height, |x, y| {
+        Rgb([((x + width * y) * 16 + 8) as u8, 0, 0])
+    });
+    let texture = Texture::linear(DynamicImage::ImageRgb8(img));
+    assert_eq!((texture.width(), texture.height()), (4., 2.));
+    // 以左上为原点的纹素
+    let texel = |x: u32, y: u32| vec3(0., 0., ((x + width * y) * 16 + 8) as f32 / 255.);
+    let border = Vec3::splat(0.5);
+    // 纹理坐标以左下为原点，依次落在纹素 (4, 0)、(-1, -1)、(9, 3)、(2, 1) 上
+    let uvs = [
+        vec2(1.125, 0.75),
+        vec2(-0.125, 1.25),
+        vec2(2.375, -0.75),
+        vec2(0.625, 0.25),
+    ];
+    for (wrap, expected, edge) in [
+        (
+            Wrap::Repeat,
+            [texel(0, 0), texel(3, 1), texel(1, 1), texel(2, 1)],
+            texel(3, 0).lerp(texel(0, 0), 0.5),
+        ),
+        (
+            Wrap::MirroredRepeat,
+            [texel(3, 0), texel(0, 0), texel(1, 0), texel(2, 1)],
+            texel(0, 0),
+        ),
+        (
+            Wrap::ClampToEdge,
+            [texel(3, 0), texel(0, 0), texel(3, 1), texel(2, 1)],
+            texel(0, 0),
+        ),
+        (
+            Wrap::ClampToBorder,
+            [border, border, border, texel(2, 1)],
+            border.lerp(texel(0, 0), 0.5),
+        ),
+    ] {
+        let nearest = Sampler::new(Filter::Nearest).wrap(wrap).border(border);
+        for (uv, expected) in uvs.into_iter().zip(expected) {
+            let actual = texture.sample(&nearest, uv, Vec2::ZERO, Vec2::ZERO);
+            assert!(
+                actual.abs_diff_eq(expected, 1e-5),
+                "{wrap:?} 在 {uv} 处期望 {expected}，实际 {actual}"
+            );
+        }
+        // 左边缘上，双线性插值一半取自越界的纹素
+        let bilinear = Sampler::new(Filter::Bilinear).wrap(wrap).border(border);
+        let actual = texture.sample(&bilinear, vec2(0., 0.75), Vec2::ZERO, Vec2::ZERO);
+        assert!(
+            actual.abs_diff_eq(edge, 1e-5),
+            "{wrap:?} 在左边缘期望 {edge}，实际 {actual}"
+        );
+    }
+}